@@ -0,0 +1,808 @@
+//! Bytecode chunks: the instruction stream, constant pool, and a run-length
+//! encoded line table, plus the disassembler.
+
+use alloc::format;
+use alloc::vec;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+use strum::{FromRepr, VariantNames};
+
+use crate::value::Value;
+
+/// Maximum constants per chunk; operands are a single byte.
+pub const MAX_CONSTANTS: usize = 256;
+
+pub const N_OPCODES: usize = <OpCode as VariantNames>::VARIANTS.len();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr, VariantNames)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    /// pushes the float `0` without touching the constant pool
+    Zero,
+    /// pushes the float `1` without touching the constant pool
+    One,
+    Pop,
+    /// pushes a copy of the top of the stack
+    Dup,
+    StackSub,
+    ReadLocal,
+    WriteLocal,
+    DefineGlobal,
+    ReadGlobal,
+    WriteGlobal,
+    ReadUpval,
+    WriteUpval,
+    ReadProperty,
+    WriteProperty,
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Not,
+    Negate,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    BitNot,
+    Print,
+    Jump,
+    JumpFalsey,
+    JumpTruthy,
+    /// `and`: jumps when falsey keeping the value, otherwise pops it
+    JumpFalseyOrPop,
+    JumpFalseyPop,
+    /// `or`: jumps when truthy keeping the value, otherwise pops it
+    JumpTruthyOrPop,
+    JumpTruthyPop,
+    JumpBack,
+    Call,
+    Closure,
+    CloseUpval,
+    Return,
+    Class,
+    Method,
+    StaticMethod,
+    Inherit,
+    Super,
+    /// fused `ReadProperty` + `Call`: operands are the method-name constant
+    /// and the arg count
+    Invoke,
+    /// fused `Super` + `Call`: operands are the method-name constant and the
+    /// arg count
+    SuperInvoke,
+    List,
+    IndexGet,
+    IndexSet,
+    ListLen,
+    /// begins a `try` region; the operand is the forward distance to the
+    /// catch block
+    PushHandler,
+    PopHandler,
+    /// pops a value and unwinds to the nearest handler with it
+    Throw,
+    // 32-bit variants used when a jump distance exceeds `u16::MAX`
+    JumpLong,
+    JumpFalseyLong,
+    JumpTruthyLong,
+    JumpFalseyOrPopLong,
+    JumpFalseyPopLong,
+    JumpTruthyOrPopLong,
+    JumpTruthyPopLong,
+    JumpBackLong,
+    PushHandlerLong,
+}
+
+impl OpCode {
+    /// Instruction length in bytes, including the opcode itself. `Closure` is
+    /// variable length (upvalue descriptors follow) — this returns its fixed
+    /// prefix.
+    pub fn total_size(&self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::ReadLocal
+            | OpCode::WriteLocal
+            | OpCode::DefineGlobal
+            | OpCode::ReadGlobal
+            | OpCode::WriteGlobal
+            | OpCode::ReadUpval
+            | OpCode::WriteUpval
+            | OpCode::ReadProperty
+            | OpCode::WriteProperty
+            | OpCode::Call
+            | OpCode::List
+            | OpCode::Closure
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::StaticMethod
+            | OpCode::StackSub
+            | OpCode::Super => 2,
+            OpCode::Invoke | OpCode::SuperInvoke => 3,
+            OpCode::Jump
+            | OpCode::JumpFalsey
+            | OpCode::JumpTruthy
+            | OpCode::JumpFalseyOrPop
+            | OpCode::JumpFalseyPop
+            | OpCode::JumpTruthyOrPop
+            | OpCode::JumpTruthyPop
+            | OpCode::JumpBack
+            | OpCode::PushHandler => 3,
+            OpCode::PushHandlerLong
+            | OpCode::JumpLong
+            | OpCode::JumpFalseyLong
+            | OpCode::JumpTruthyLong
+            | OpCode::JumpFalseyOrPopLong
+            | OpCode::JumpFalseyPopLong
+            | OpCode::JumpTruthyOrPopLong
+            | OpCode::JumpTruthyPopLong
+            | OpCode::JumpBackLong => 5,
+            _ => 1,
+        }
+    }
+}
+
+/// One decoded instruction in a machine-readable listing: jump operands are
+/// resolved to absolute target offsets, everything else is the raw operand
+/// bytes' numeric values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingEntry {
+    pub offset: usize,
+    pub line: u32,
+    pub opcode: &'static str,
+    pub operands: Vec<usize>,
+}
+
+/// Debug record mapping a local's name to its frame slot, retained only
+/// with the `debug_info` feature so hosts can resolve locals by name.
+#[cfg(feature = "debug_info")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalInfo {
+    pub name: String,
+    pub slot: u8,
+    /// first chunk offset at which the local is live (past its initializer)
+    pub scope_start_offset: usize,
+    /// offset of the code that pops the local; one past its last live byte
+    pub scope_end_offset: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// run-length encoded as `(end_offset, line)`: the run covers bytes from
+    /// the previous entry's end (or 0) up to `end_offset`. Cumulative ends
+    /// keep `line_for_offset` a binary search.
+    lines: Vec<(u32, u32)>,
+    /// the source text this chunk was compiled from, for error reporting
+    pub source: Rc<str>,
+    /// per-local debug records, in the order their scopes closed
+    #[cfg(feature = "debug_info")]
+    pub locals: Vec<LocalInfo>,
+}
+
+impl Chunk {
+    pub fn new(source: Rc<str>) -> Self {
+        Self {
+            data: Vec::new(),
+            constants: Vec::new(),
+            lines: Vec::new(),
+            source,
+            #[cfg(feature = "debug_info")]
+            locals: Vec::new(),
+        }
+    }
+
+    /// Debug records of the locals live at `offset`, for debugger variable
+    /// views and watch expressions.
+    #[cfg(feature = "debug_info")]
+    pub fn locals_at(&self, offset: usize) -> impl Iterator<Item = &LocalInfo> {
+        self.locals
+            .iter()
+            .filter(move |l| l.scope_start_offset <= offset && offset < l.scope_end_offset)
+    }
+
+    pub fn push_op(&mut self, op: OpCode, line: u32) {
+        self.push_byte(op as u8, line);
+    }
+
+    pub fn push_byte(&mut self, byte: u8, line: u32) {
+        self.data.push(byte);
+        let end = self.data.len() as u32;
+        match self.lines.last_mut() {
+            Some((e, l)) if *l == line => *e = end,
+            _ => self.lines.push((end, line)),
+        }
+    }
+
+    /// Emits a forward jump with a placeholder offset, returning the offset of
+    /// the operand for later patching.
+    pub fn push_jump(&mut self, op: OpCode, line: u32) -> usize {
+        self.push_op(op, line);
+        self.push_byte(0xff, line);
+        self.push_byte(0xff, line);
+        self.data.len() - 2
+    }
+
+    /// Patches the placeholder emitted by `push_jump` to land on the current
+    /// end of the chunk. When the distance exceeds `u16::MAX` the jump is
+    /// rewritten in its 32-bit long form, which shifts all later code by two
+    /// bytes; the returned positions record where bytes were inserted so the
+    /// compiler can relocate any chunk offsets it still holds.
+    pub fn patch_jump(&mut self, operand_pos: usize) -> Vec<usize> {
+        // the distance from the operand's end to the chunk's end is the same
+        // in both encodings: widening moves the target and the operand end
+        // by the same amount
+        let offset = self.data.len() - operand_pos - 2;
+        // an offset of exactly 0xffff must take the long form: the short
+        // encoding would be byte-identical to an unpatched placeholder,
+        // which `widen_jump` skips when adjusting spanning jumps
+        if offset < u16::MAX as usize {
+            let bytes = (offset as u16).to_le_bytes();
+            self.data[operand_pos] = bytes[0];
+            self.data[operand_pos + 1] = bytes[1];
+            Vec::new()
+        } else {
+            self.widen_jump(operand_pos, offset as u32)
+        }
+    }
+
+    /// Emits a backward jump to `loop_start` (an offset into `data`), using
+    /// the long form when the distance requires it.
+    pub fn push_loop(&mut self, loop_start: usize, line: u32) {
+        if self.data.len() + 3 - loop_start <= u16::MAX as usize {
+            self.push_op(OpCode::JumpBack, line);
+            let bytes = ((self.data.len() + 2 - loop_start) as u16).to_le_bytes();
+            self.push_byte(bytes[0], line);
+            self.push_byte(bytes[1], line);
+        } else {
+            self.push_op(OpCode::JumpBackLong, line);
+            let bytes = ((self.data.len() + 4 - loop_start) as u32).to_le_bytes();
+            for byte in bytes {
+                self.push_byte(byte, line);
+            }
+        }
+    }
+
+    /// Rewrites the short jump whose operand starts at `operand_pos` as its
+    /// long variant carrying `offset`, inserting two bytes at the operand.
+    /// Every patched jump spanning the insertion point is adjusted; in the
+    /// pathological case where that adjustment overflows another short jump,
+    /// the widening cascades. Returns each insertion point applied.
+    fn widen_jump(&mut self, operand_pos: usize, offset: u32) -> Vec<usize> {
+        let insert_pos = operand_pos + 2;
+
+        // collect spanning jumps before shifting anything
+        let mut fitting = Vec::new();
+        let mut overflowing = Vec::new();
+        let mut pos = 0;
+        while pos < self.data.len() {
+            let op = OpCode::from_repr(self.data[pos]).expect("invalid opcode");
+            let size = self.instr_size(pos);
+            if pos + 1 != operand_pos {
+                match op {
+                    OpCode::Jump
+                    | OpCode::JumpFalsey
+                    | OpCode::JumpTruthy
+                    | OpCode::JumpFalseyOrPop
+                    | OpCode::JumpFalseyPop
+                    | OpCode::JumpTruthyOrPop
+                    | OpCode::JumpTruthyPop
+                    | OpCode::PushHandler => {
+                        let off = u16::from_le_bytes([self.data[pos + 1], self.data[pos + 2]]);
+                        let end = pos + 3;
+                        // 0xffff is the unpatched placeholder; those jumps
+                        // resolve against the shifted chunk end on their own
+                        if off != u16::MAX && end <= insert_pos && end + off as usize >= insert_pos
+                        {
+                            if off as u32 + 2 > u16::MAX as u32 {
+                                overflowing.push(pos + 1);
+                            } else {
+                                fitting.push((pos + 1, false, off as u32 + 2));
+                            }
+                        }
+                    }
+                    OpCode::JumpLong
+                    | OpCode::JumpFalseyLong
+                    | OpCode::JumpTruthyLong
+                    | OpCode::JumpFalseyOrPopLong
+                    | OpCode::JumpFalseyPopLong
+                    | OpCode::JumpTruthyOrPopLong
+                    | OpCode::JumpTruthyPopLong
+                    | OpCode::PushHandlerLong => {
+                        let off = u32::from_le_bytes(
+                            self.data[pos + 1..pos + 5].try_into().unwrap(),
+                        );
+                        let end = pos + 5;
+                        if end <= insert_pos && end + off as usize >= insert_pos {
+                            fitting.push((pos + 1, true, off + 2));
+                        }
+                    }
+                    OpCode::JumpBack => {
+                        let off = u16::from_le_bytes([self.data[pos + 1], self.data[pos + 2]]);
+                        let end = pos + 3;
+                        if end > insert_pos && end - (off as usize) < insert_pos {
+                            if off as u32 + 2 > u16::MAX as u32 {
+                                overflowing.push(pos + 1);
+                            } else {
+                                fitting.push((pos + 1, false, off as u32 + 2));
+                            }
+                        }
+                    }
+                    OpCode::JumpBackLong => {
+                        let off = u32::from_le_bytes(
+                            self.data[pos + 1..pos + 5].try_into().unwrap(),
+                        );
+                        let end = pos + 5;
+                        if end > insert_pos && end - (off as usize) < insert_pos {
+                            fitting.push((pos + 1, true, off + 2));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            pos += size;
+        }
+
+        let long = match OpCode::from_repr(self.data[operand_pos - 1]).unwrap() {
+            OpCode::Jump => OpCode::JumpLong,
+            OpCode::JumpFalsey => OpCode::JumpFalseyLong,
+            OpCode::JumpTruthy => OpCode::JumpTruthyLong,
+            OpCode::JumpFalseyOrPop => OpCode::JumpFalseyOrPopLong,
+            OpCode::JumpFalseyPop => OpCode::JumpFalseyPopLong,
+            OpCode::JumpTruthyOrPop => OpCode::JumpTruthyOrPopLong,
+            OpCode::JumpTruthyPop => OpCode::JumpTruthyPopLong,
+            OpCode::JumpBack => OpCode::JumpBackLong,
+            OpCode::PushHandler => OpCode::PushHandlerLong,
+            op => unreachable!("cannot widen {op:?}"),
+        };
+        self.data[operand_pos - 1] = long as u8;
+        self.data.splice(insert_pos..insert_pos, [0, 0]);
+        self.data[operand_pos..operand_pos + 4].copy_from_slice(&offset.to_le_bytes());
+        self.grow_line_run(operand_pos, 2);
+
+        let mut insertions = vec![insert_pos];
+        for (p, is_long, new_off) in fitting {
+            let p = if p >= insert_pos { p + 2 } else { p };
+            if is_long {
+                self.data[p..p + 4].copy_from_slice(&new_off.to_le_bytes());
+            } else {
+                self.data[p..p + 2].copy_from_slice(&(new_off as u16).to_le_bytes());
+            }
+        }
+        let mut overflowing: Vec<usize> = overflowing
+            .into_iter()
+            .map(|p| if p >= insert_pos { p + 2 } else { p })
+            .collect();
+        while let Some(p) = overflowing.pop() {
+            // a cascade may have widened this jump already
+            if OpCode::from_repr(self.data[p - 1]).unwrap().total_size() == 5 {
+                continue;
+            }
+            let off = u16::from_le_bytes([self.data[p], self.data[p + 1]]) as u32;
+            let nested = self.widen_jump(p, off + 2);
+            for later in &mut overflowing {
+                for ins in &nested {
+                    if *later >= *ins {
+                        *later += 2;
+                    }
+                }
+            }
+            insertions.extend(nested);
+        }
+        insertions
+    }
+
+    /// Byte length of the instruction at `offset`, accounting for `Closure`'s
+    /// trailing upvalue descriptors.
+    fn instr_size(&self, offset: usize) -> usize {
+        let op = OpCode::from_repr(self.data[offset]).expect("invalid opcode");
+        let mut size = op.total_size();
+        if op == OpCode::Closure {
+            if let Value::Function(f) = &self.constants[self.data[offset + 1] as usize] {
+                size += 2 * f.upval_count;
+            }
+        }
+        size
+    }
+
+    /// Widens the line-table run covering `byte_pos` by `by` bytes, shifting
+    /// every later run's end along with it.
+    fn grow_line_run(&mut self, byte_pos: usize, by: u32) {
+        let idx = self.lines.partition_point(|(end, _)| (*end as usize) <= byte_pos);
+        let idx = idx.min(self.lines.len().saturating_sub(1));
+        for (end, _) in &mut self.lines[idx..] {
+            *end += by;
+        }
+    }
+
+    /// Rolls the chunk back to an earlier state, discarding emitted code.
+    /// Used by the compiler to drop unreachable statements; the compiler
+    /// rolls the constant pool back itself.
+    pub fn truncate(&mut self, data_len: usize) {
+        self.data.truncate(data_len);
+        let idx = self.lines.partition_point(|(end, _)| (*end as usize) <= data_len);
+        let prev_end = idx.checked_sub(1).map_or(0, |i| self.lines[i].0 as usize);
+        if idx < self.lines.len() && data_len > prev_end {
+            // the run straddling the cut survives, clamped to the new length
+            self.lines.truncate(idx + 1);
+            self.lines[idx].0 = data_len as u32;
+        } else {
+            self.lines.truncate(idx);
+        }
+    }
+
+    pub fn line_for_offset(&self, offset: usize) -> u32 {
+        let idx = self.lines.partition_point(|(end, _)| (*end as usize) <= offset);
+        self.lines
+            .get(idx)
+            .or(self.lines.last())
+            .map(|(_, l)| *l)
+            .unwrap_or(0)
+    }
+
+    /// Decodes the chunk into structured [`ListingEntry`] records for
+    /// external tooling, as opposed to the human-readable [`disassemble`]
+    /// text.
+    ///
+    /// [`disassemble`]: Self::disassemble
+    /// The 1-based `line`-th line of the chunk's source, without its
+    /// terminator. Empty when out of range.
+    pub fn source_line(&self, line: u32) -> &str {
+        self.source
+            .lines()
+            .nth((line.max(1) - 1) as usize)
+            .unwrap_or("")
+    }
+
+    pub fn to_listing(&self) -> Vec<ListingEntry> {
+        let mut listing = Vec::new();
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let Some(op) = OpCode::from_repr(self.data[offset]) else {
+                offset += 1;
+                continue;
+            };
+            let next = offset + self.instr_size(offset);
+            let operands = match op {
+                OpCode::Jump
+                | OpCode::JumpFalsey
+                | OpCode::JumpTruthy
+                | OpCode::JumpFalseyOrPop
+                | OpCode::JumpFalseyPop
+                | OpCode::JumpTruthyOrPop
+                | OpCode::JumpTruthyPop
+                | OpCode::PushHandler => {
+                    let jump =
+                        u16::from_le_bytes([self.data[offset + 1], self.data[offset + 2]]);
+                    vec![offset + 3 + jump as usize]
+                }
+                OpCode::JumpBack => {
+                    let jump =
+                        u16::from_le_bytes([self.data[offset + 1], self.data[offset + 2]]);
+                    vec![offset + 3 - jump as usize]
+                }
+                OpCode::JumpLong
+                | OpCode::JumpFalseyLong
+                | OpCode::JumpTruthyLong
+                | OpCode::JumpFalseyOrPopLong
+                | OpCode::JumpFalseyPopLong
+                | OpCode::JumpTruthyOrPopLong
+                | OpCode::JumpTruthyPopLong
+                | OpCode::PushHandlerLong => {
+                    let jump =
+                        u32::from_le_bytes(self.data[offset + 1..offset + 5].try_into().unwrap());
+                    vec![offset + 5 + jump as usize]
+                }
+                OpCode::JumpBackLong => {
+                    let jump =
+                        u32::from_le_bytes(self.data[offset + 1..offset + 5].try_into().unwrap());
+                    vec![offset + 5 - jump as usize]
+                }
+                _ => self.data[offset + 1..next].iter().map(|b| *b as usize).collect(),
+            };
+            listing.push(ListingEntry {
+                offset,
+                line: self.line_for_offset(offset),
+                opcode: <OpCode as VariantNames>::VARIANTS[self.data[offset] as usize],
+                operands,
+            });
+            offset = next;
+        }
+        listing
+    }
+
+    pub fn disassemble(&self, name: &str) -> String {
+        // mirrors `disassemble_to`, but infallibly into a `String` so it is
+        // available without `std`
+        let mut out = String::new();
+        if self.data.is_empty() {
+            let _ = writeln!(out, "== {name} ==");
+        } else {
+            let _ = writeln!(out, "== {name} == (line {})", self.line_for_offset(0));
+        }
+        let mut offset = 0;
+        let mut last_line = u32::MAX;
+        while offset < self.data.len() {
+            let (text, next) = self.disassemble_instr(offset);
+            let line = self.line_for_offset(offset);
+            if line != last_line {
+                let _ = writeln!(out, "Line {line}:");
+                last_line = line;
+            }
+            let _ = writeln!(out, "{text}");
+            offset = next;
+        }
+        out
+    }
+
+    /// Writes the disassembly incrementally instead of buffering it all in
+    /// one `String`, for piping large dumps to a file or stderr.
+    #[cfg(feature = "std")]
+    pub fn disassemble_to(&self, w: &mut impl std::io::Write, name: &str) -> std::io::Result<()> {
+        // each chunk gets its own header and line tracking: nested functions
+        // carry separate line tables, so a combined dump stays accurate
+        if self.data.is_empty() {
+            writeln!(w, "== {name} ==")?;
+        } else {
+            writeln!(w, "== {name} == (line {})", self.line_for_offset(0))?;
+        }
+        let mut offset = 0;
+        let mut last_line = u32::MAX;
+        while offset < self.data.len() {
+            let (text, next) = self.disassemble_instr(offset);
+            let line = self.line_for_offset(offset);
+            if line != last_line {
+                writeln!(w, "Line {line}:")?;
+                last_line = line;
+            }
+            writeln!(w, "{text}")?;
+            offset = next;
+        }
+        Ok(())
+    }
+
+    /// Renders the instruction at `offset`, returning the text and the offset
+    /// of the next instruction.
+    pub fn disassemble_instr(&self, offset: usize) -> (String, usize) {
+        let byte = self.data[offset];
+        let Some(op) = OpCode::from_repr(byte) else {
+            return (format!("{offset:04}    <unknown {byte:#04x}>"), offset + 1);
+        };
+        let mut next = offset + op.total_size();
+        let text = match op {
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::ReadGlobal
+            | OpCode::WriteGlobal
+            | OpCode::ReadProperty
+            | OpCode::WriteProperty
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::StaticMethod
+            | OpCode::Super => {
+                let idx = self.data[offset + 1];
+                format!(
+                    "{offset:04}    {op:?} [{idx}] ({})",
+                    self.constants[idx as usize]
+                )
+            }
+            OpCode::ReadLocal | OpCode::WriteLocal | OpCode::ReadUpval | OpCode::WriteUpval => {
+                let slot = self.data[offset + 1];
+                format!("{offset:04}    {op:?} [{slot}]")
+            }
+            OpCode::Call => {
+                let args = self.data[offset + 1];
+                format!("{offset:04}    {op:?} ({args} args)")
+            }
+            OpCode::List => {
+                let count = self.data[offset + 1];
+                format!("{offset:04}    {op:?} ({count} elements)")
+            }
+            OpCode::StackSub => {
+                let count = self.data[offset + 1];
+                format!("{offset:04}    {op:?} ({count} slots)")
+            }
+            OpCode::Invoke | OpCode::SuperInvoke => {
+                let idx = self.data[offset + 1];
+                let args = self.data[offset + 2];
+                format!(
+                    "{offset:04}    {op:?} [{idx}] ({}) ({args} args)",
+                    self.constants[idx as usize]
+                )
+            }
+            OpCode::Jump
+            | OpCode::JumpFalsey
+            | OpCode::JumpTruthy
+            | OpCode::JumpFalseyOrPop
+            | OpCode::JumpFalseyPop
+            | OpCode::JumpTruthyOrPop
+            | OpCode::JumpTruthyPop
+            | OpCode::PushHandler => {
+                let jump = u16::from_le_bytes([self.data[offset + 1], self.data[offset + 2]]);
+                format!(
+                    "{offset:04}    {op:?} -> {}",
+                    offset + 3 + jump as usize
+                )
+            }
+            OpCode::JumpBack => {
+                let jump = u16::from_le_bytes([self.data[offset + 1], self.data[offset + 2]]);
+                format!(
+                    "{offset:04}    {op:?} -> {}",
+                    offset + 3 - jump as usize
+                )
+            }
+            OpCode::JumpLong
+            | OpCode::JumpFalseyLong
+            | OpCode::JumpTruthyLong
+            | OpCode::JumpFalseyOrPopLong
+            | OpCode::JumpFalseyPopLong
+            | OpCode::JumpTruthyOrPopLong
+            | OpCode::JumpTruthyPopLong
+            | OpCode::PushHandlerLong => {
+                let jump =
+                    u32::from_le_bytes(self.data[offset + 1..offset + 5].try_into().unwrap());
+                format!("{offset:04}    {op:?} -> {}", offset + 5 + jump as usize)
+            }
+            OpCode::JumpBackLong => {
+                let jump =
+                    u32::from_le_bytes(self.data[offset + 1..offset + 5].try_into().unwrap());
+                format!("{offset:04}    {op:?} -> {}", offset + 5 - jump as usize)
+            }
+            OpCode::Closure => {
+                let idx = self.data[offset + 1];
+                let constant = &self.constants[idx as usize];
+                let mut s = format!("{offset:04}    {op:?} [{idx}] ({constant})");
+                let upval_count = match constant {
+                    Value::Function(f) => f.upval_count,
+                    _ => 0,
+                };
+                for _ in 0..upval_count {
+                    let is_local = self.data[next];
+                    let index = self.data[next + 1];
+                    let _ = write!(
+                        s,
+                        "\n{next:04}    |    {} {index}",
+                        if is_local == 1 { "local" } else { "upval" }
+                    );
+                    next += 2;
+                }
+                s
+            }
+            _ => format!("{offset:04}    {op:?}"),
+        };
+        (text, next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listing_decodes_opcodes_and_operands() {
+        let mut chunk = Chunk::new(Rc::from(""));
+        chunk.constants.push(Value::Float(1.0));
+        chunk.push_op(OpCode::Constant, 1);
+        chunk.push_byte(0, 1);
+        let operand = chunk.push_jump(OpCode::JumpFalsey, 1);
+        chunk.push_op(OpCode::Pop, 2);
+        chunk.patch_jump(operand);
+        chunk.push_op(OpCode::Return, 3);
+        let listing = chunk.to_listing();
+        let summary: Vec<_> = listing
+            .iter()
+            .map(|e| (e.offset, e.opcode, e.operands.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, "Constant", vec![0]),
+                (2, "JumpFalsey", vec![6]),
+                (5, "Pop", vec![]),
+                (6, "Return", vec![]),
+            ]
+        );
+        assert_eq!(listing[3].line, 3);
+    }
+
+    #[test]
+    fn disassemble_to_matches_string_version() {
+        let mut chunk = Chunk::new(Rc::from(""));
+        chunk.constants.push(Value::Float(7.0));
+        chunk.push_op(OpCode::Constant, 1);
+        chunk.push_byte(0, 1);
+        chunk.push_op(OpCode::Negate, 2);
+        chunk.push_op(OpCode::Return, 2);
+        let mut buf = Vec::new();
+        chunk.disassemble_to(&mut buf, "test").unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), chunk.disassemble("test"));
+    }
+
+    #[test]
+    fn disassembles_invoke_operands() {
+        let mut chunk = Chunk::new(Rc::from(""));
+        chunk.constants.push(Value::Float(0.0));
+        chunk.push_op(OpCode::Invoke, 1);
+        chunk.push_byte(0, 1);
+        chunk.push_byte(2, 1);
+        chunk.push_op(OpCode::SuperInvoke, 1);
+        chunk.push_byte(0, 1);
+        chunk.push_byte(3, 1);
+        let (text, next) = chunk.disassemble_instr(0);
+        assert_eq!(text, "0000    Invoke [0] (0) (2 args)");
+        assert_eq!(next, 3);
+        let (text, next) = chunk.disassemble_instr(3);
+        assert_eq!(text, "0003    SuperInvoke [0] (0) (3 args)");
+        assert_eq!(next, 6);
+    }
+
+    #[test]
+    fn jump_of_exactly_u16_max_takes_the_long_form() {
+        let mut chunk = Chunk::new(Rc::from(""));
+        let a = chunk.push_jump(OpCode::Jump, 1);
+        let mut b = chunk.push_jump(OpCode::Jump, 1);
+        // pad so A's patched offset lands exactly on u16::MAX
+        while chunk.data.len() < a + 2 + u16::MAX as usize {
+            chunk.push_op(OpCode::Pop, 1);
+        }
+        for moved in chunk.patch_jump(a) {
+            if moved <= b {
+                b += 2;
+            }
+        }
+        // the short encoding of 0xffff is indistinguishable from an
+        // unpatched placeholder, so A must have been widened
+        assert_eq!(OpCode::from_repr(chunk.data[a - 1]), Some(OpCode::JumpLong));
+        let off_a = u32::from_le_bytes(chunk.data[a..a + 4].try_into().unwrap());
+        assert_eq!(off_a, u16::MAX as u32);
+        let target_a = a + 4 + off_a as usize;
+        assert_eq!(target_a, chunk.data.len());
+
+        // push B's target out of short range; its widening inserts two
+        // bytes inside A's span, which must cascade into A's offset
+        for _ in 0..10 {
+            chunk.push_op(OpCode::Pop, 1);
+        }
+        chunk.patch_jump(b);
+        assert_eq!(OpCode::from_repr(chunk.data[b - 1]), Some(OpCode::JumpLong));
+        let off_b = u32::from_le_bytes(chunk.data[b..b + 4].try_into().unwrap());
+        assert_eq!(b + 4 + off_b as usize, chunk.data.len());
+        let off_a = u32::from_le_bytes(chunk.data[a..a + 4].try_into().unwrap());
+        assert_eq!(a + 4 + off_a as usize, target_a + 2);
+    }
+
+    #[test]
+    fn line_for_offset_matches_linear_scan() {
+        let mut chunk = Chunk::new(Rc::from(""));
+        // uneven run lengths across a few hundred lines
+        for line in 1..=300u32 {
+            for _ in 0..(line % 7 + 1) {
+                chunk.push_byte(0, line);
+            }
+        }
+        let linear = |offset: usize| -> u32 {
+            let mut start = 0usize;
+            for (end, line) in &chunk.lines {
+                if offset >= start && offset < *end as usize {
+                    return *line;
+                }
+                start = *end as usize;
+            }
+            chunk.lines.last().map(|(_, l)| *l).unwrap_or(0)
+        };
+        for offset in 0..chunk.data.len() + 2 {
+            assert_eq!(chunk.line_for_offset(offset), linear(offset), "offset {offset}");
+        }
+    }
+}
@@ -0,0 +1,1710 @@
+//! Single-pass compiler: a Pratt parser that emits bytecode directly into the
+//! current function's chunk. `Parser` drives scanning and error reporting;
+//! `Compiler` holds per-function state (locals, upvalues, scope depth) and
+//! forms a stack via `enclosing` while nested functions compile.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::chunk::{Chunk, OpCode, MAX_CONSTANTS};
+use crate::scanner::{Scanner, Token, TokenKind};
+use crate::table::Table;
+use crate::value::{alloc_str, Function, LoxStr, Value};
+use crate::vm::{GCStats, InterpretError};
+
+pub const MAX_LOCALS: usize = 256;
+pub const MAX_UPVAL: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equality,
+    Comparison,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Shift,
+            Precedence::Shift => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn<'src, 'vm> = fn(&mut Parser<'src, 'vm>, bool);
+
+struct ParseRule<'src, 'vm> {
+    prefix: Option<ParseFn<'src, 'vm>>,
+    infix: Option<ParseFn<'src, 'vm>>,
+    precedence: Precedence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Local<'src> {
+    name: &'src str,
+    /// scope depth, or -1 while the initializer is still being compiled
+    depth: i32,
+    captured: bool,
+    /// set when the local is read (or captured); unused locals warn when
+    /// their scope ends
+    used: bool,
+    line: u32,
+    /// chunk offset at which the local became live, for debug records
+    #[cfg(feature = "debug_info")]
+    start_offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct UpvalDesc {
+    index: u8,
+    is_local: bool,
+}
+
+pub struct Compiler<'src> {
+    enclosing: Option<Box<Compiler<'src>>>,
+    function: Function,
+    kind: FunKind,
+    locals: Vec<Local<'src>>,
+    upvalues: Vec<UpvalDesc>,
+    /// chunk offsets (pending jump operands, loop targets) that must stay
+    /// valid when long-jump widening shifts later code
+    tracked_positions: Vec<usize>,
+    scope_depth: i32,
+}
+
+impl<'src> Compiler<'src> {
+    /// Retains `name`'s frame slot and scope extent in the chunk's debug
+    /// records. The scope closes at the current chunk offset.
+    #[cfg(feature = "debug_info")]
+    fn record_local(&mut self, name: &str, slot: usize, start_offset: usize) {
+        if name.is_empty() {
+            // compiler-synthesized temporaries have no name to look up
+            return;
+        }
+        let end = self.function.chunk.data.len();
+        self.function.chunk.locals.push(crate::chunk::LocalInfo {
+            name: name.to_string(),
+            slot: slot as u8,
+            scope_start_offset: start_offset,
+            scope_end_offset: end,
+        });
+    }
+
+    /// Flushes debug records for every still-live local. Called when a
+    /// function ends, since body locals never pass through `end_scope`.
+    #[cfg(feature = "debug_info")]
+    fn record_remaining_locals(&mut self) {
+        for slot in 0..self.locals.len() {
+            let name = self.locals[slot].name.to_string();
+            let start = self.locals[slot].start_offset;
+            self.record_local(&name, slot, start);
+        }
+    }
+
+    fn new(kind: FunKind, name: Option<LoxStr>, source: Rc<str>, constants: Vec<Value>) -> Self {
+        // slot 0 holds the function itself, or `this` inside methods
+        let slot_zero = Local {
+            name: if matches!(kind, FunKind::Method | FunKind::Initializer) {
+                "this"
+            } else {
+                ""
+            },
+            depth: 0,
+            captured: false,
+            used: true,
+            line: 0,
+            #[cfg(feature = "debug_info")]
+            start_offset: 0,
+        };
+        let mut function = Function::new(name, source);
+        function.chunk.constants = constants;
+        Self {
+            enclosing: None,
+            function,
+            kind,
+            locals: vec![slot_zero],
+            upvalues: Vec::new(),
+            tracked_positions: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+/// Constant-pool deduplication. Floats compare by bit pattern so repeated
+/// `nan` literals share a slot (IEEE equality would never dedup them) and
+/// `0.0`/`-0.0` keep their distinct signs.
+fn constants_match(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+/// Resolves `name` to a local slot in `compiler`, or errors if the local is
+/// read inside its own initializer.
+fn resolve_local(compiler: &Compiler, name: &str) -> Result<Option<u8>, String> {
+    for (i, local) in compiler.locals.iter().enumerate().rev() {
+        if local.name == name {
+            if local.depth == -1 {
+                return Err(format!(
+                    "Cannot read local variable '{name}' in its own initializer."
+                ));
+            }
+            return Ok(Some(i as u8));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolves `name` as an upvalue of `compiler`, capturing it through each
+/// enclosing function as needed.
+fn resolve_upvalue(compiler: &mut Compiler, name: &str) -> Result<Option<u8>, String> {
+    let Some(enclosing) = compiler.enclosing.as_deref_mut() else {
+        return Ok(None);
+    };
+    if let Some(local) = resolve_local(enclosing, name)? {
+        enclosing.locals[local as usize].captured = true;
+        enclosing.locals[local as usize].used = true;
+        return Ok(Some(add_upvalue(compiler, local, true)?));
+    }
+    if let Some(upval) = resolve_upvalue(enclosing, name)? {
+        return Ok(Some(add_upvalue(compiler, upval, false)?));
+    }
+    Ok(None)
+}
+
+fn add_upvalue(compiler: &mut Compiler, index: u8, is_local: bool) -> Result<u8, String> {
+    let desc = UpvalDesc { index, is_local };
+    if let Some(i) = compiler.upvalues.iter().position(|u| *u == desc) {
+        return Ok(i as u8);
+    }
+    if compiler.upvalues.len() >= MAX_UPVAL {
+        return Err("Too many closure variables in function.".to_string());
+    }
+    compiler.upvalues.push(desc);
+    compiler.function.upval_count = compiler.upvalues.len();
+    Ok((compiler.upvalues.len() - 1) as u8)
+}
+
+/// Flags a local that was declared but never read. Underscore-prefixed
+/// names and compiler-synthesized locals are exempt.
+fn warn_unused(local: &Local) {
+    if !local.used && !local.name.is_empty() && !local.name.starts_with('_') && local.name != "super"
+    {
+        tracing::warn!(
+            "[line {}] Unused local variable '{}'.",
+            local.line,
+            local.name
+        );
+    }
+}
+
+struct ClassCtx {
+    has_superclass: bool,
+    /// true while compiling a static method, where `this` is meaningless
+    in_static: bool,
+}
+
+pub struct Parser<'src, 'vm> {
+    scanner: Scanner<'src>,
+    pub prev: Token<'src>,
+    pub current: Token<'src>,
+    errors: bool,
+    panic_mode: bool,
+    /// set by an unconditional `return`; the rest of the enclosing block is
+    /// parsed but not emitted
+    terminated: bool,
+    /// doc comment preceding `current` / `prev`, shifted along by `advance`
+    current_doc: Option<&'src str>,
+    prev_doc: Option<&'src str>,
+    diagnostics: Vec<String>,
+    compiler: Box<Compiler<'src>>,
+    /// constants shared by every chunk in this compilation; each compiler's
+    /// chunk mirrors it so slot indices line up across functions
+    constant_pool: Vec<Value>,
+    class_compilers: Vec<ClassCtx>,
+    strings: &'vm mut Table,
+    objects: &'vm mut Vec<Value>,
+    stats: &'vm mut GCStats,
+}
+
+/// Compiles `source` into the top-level script function.
+pub fn compile(
+    source: &str,
+    strings: &mut Table,
+    objects: &mut Vec<Value>,
+    stats: &mut GCStats,
+) -> Result<Function, InterpretError> {
+    let src: Rc<str> = Rc::from(source);
+    let mut parser = Parser {
+        scanner: Scanner::new(source),
+        prev: Token::empty(),
+        current: Token::empty(),
+        errors: false,
+        panic_mode: false,
+        terminated: false,
+        current_doc: None,
+        prev_doc: None,
+        diagnostics: Vec::new(),
+        compiler: Box::new(Compiler::new(FunKind::Script, None, src, Vec::new())),
+        constant_pool: Vec::new(),
+        class_compilers: Vec::new(),
+        strings,
+        objects,
+        stats,
+    };
+    parser.advance();
+    while !parser.matches(TokenKind::Eof) {
+        parser.declaration();
+    }
+    let function = parser.end_compiler();
+    if parser.errors {
+        Err(InterpretError::CompileError(parser.diagnostics.join("\n")))
+    } else {
+        Ok(function)
+    }
+}
+
+impl<'src, 'vm> Parser<'src, 'vm> {
+    // ------------------------------------------------------------------
+    // token plumbing and error reporting
+    // ------------------------------------------------------------------
+
+    fn advance(&mut self) {
+        self.prev = self.current;
+        self.prev_doc = self.current_doc.take();
+        loop {
+            self.current = self.scanner.next_token();
+            if self.current.kind != TokenKind::Error {
+                break;
+            }
+            let msg = self.current.data.to_string();
+            self.log_error_at(self.current, &msg);
+        }
+        self.current_doc = self.scanner.take_block_comment();
+    }
+
+    fn consume(&mut self, kind: TokenKind, msg: &str) {
+        if self.current.kind == kind {
+            self.advance();
+        } else {
+            self.log_error_at(self.current, msg);
+        }
+    }
+
+    fn check(&self, kind: TokenKind) -> bool {
+        self.current.kind == kind
+    }
+
+    fn matches(&mut self, kind: TokenKind) -> bool {
+        if self.check(kind) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// One token of lookahead past `current`, for disambiguating
+    /// `for (x in ...` from a C-style `for`.
+    fn peek_next(&self) -> Token<'src> {
+        self.scanner.clone().next_token()
+    }
+
+    fn log_error(&mut self, msg: &str) {
+        self.log_error_at(self.prev, msg);
+    }
+
+    fn log_error_at(&mut self, token: Token, msg: &str) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        self.errors = true;
+        let loc = match token.kind {
+            TokenKind::Eof => " at end".to_string(),
+            TokenKind::Error => String::new(),
+            _ => format!(" at '{}'", token.data),
+        };
+        let full = format!("[line {}] Error{loc}: {msg}", token.line);
+        tracing::error!("{full}");
+        self.diagnostics.push(full);
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while self.current.kind != TokenKind::Eof {
+            if self.prev.kind == TokenKind::Semicolon {
+                return;
+            }
+            match self.current.kind {
+                TokenKind::Class
+                | TokenKind::Fun
+                | TokenKind::Var
+                | TokenKind::For
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::Print
+                | TokenKind::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // emission helpers
+    // ------------------------------------------------------------------
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler.function.chunk
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        let line = self.prev.line;
+        self.chunk().push_op(op, line);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.prev.line;
+        self.chunk().push_byte(byte, line);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let idx = self.make_constant(value);
+        self.emit_op(OpCode::Constant);
+        self.emit_byte(idx);
+    }
+
+    /// Adds `value` to the shared constant pool (deduplicated), mirroring it
+    /// into every active compiler's chunk. Reports a compile error when the
+    /// pool is full and returns slot 0, which parsing past the error ignores.
+    fn make_constant(&mut self, value: Value) -> u8 {
+        if let Some(idx) = self
+            .constant_pool
+            .iter()
+            .position(|v| constants_match(v, &value))
+        {
+            return idx as u8;
+        }
+        if self.constant_pool.len() >= MAX_CONSTANTS {
+            self.log_error("Too many constants in one chunk.");
+            return 0;
+        }
+        self.constant_pool.push(value.clone());
+        let mut compiler = Some(&mut *self.compiler);
+        while let Some(c) = compiler {
+            c.function.chunk.constants.push(value.clone());
+            compiler = c.enclosing.as_deref_mut();
+        }
+        (self.constant_pool.len() - 1) as u8
+    }
+
+    fn emit_return(&mut self) {
+        if self.compiler.kind == FunKind::Initializer {
+            self.emit_op(OpCode::ReadLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    /// Emits a forward jump, returning a handle valid across any long-jump
+    /// widening; resolve it with `patch_jump`.
+    fn push_jump(&mut self, op: OpCode) -> usize {
+        let line = self.prev.line;
+        let pos = self.chunk().push_jump(op, line);
+        self.track_pos(pos)
+    }
+
+    fn patch_jump(&mut self, handle: usize) {
+        let pos = self.compiler.tracked_positions[handle];
+        for ins in self.chunk().patch_jump(pos) {
+            for tracked in &mut self.compiler.tracked_positions {
+                if *tracked >= ins {
+                    *tracked += 2;
+                }
+            }
+        }
+    }
+
+    fn push_loop(&mut self, loop_start: usize) {
+        let line = self.prev.line;
+        let pos = self.compiler.tracked_positions[loop_start];
+        self.chunk().push_loop(pos, line);
+    }
+
+    /// Registers a chunk offset to be kept in sync when widening inserts
+    /// bytes, returning a handle for later lookup.
+    fn track_pos(&mut self, pos: usize) -> usize {
+        self.compiler.tracked_positions.push(pos);
+        self.compiler.tracked_positions.len() - 1
+    }
+
+    fn intern(&mut self, s: &str) -> LoxStr {
+        alloc_str(s, self.strings, self.objects, self.stats)
+    }
+
+    fn identifier_constant(&mut self, token: Token) -> u8 {
+        let name = self.intern(token.data);
+        self.make_constant(Value::String(name))
+    }
+
+    fn end_compiler(&mut self) -> Function {
+        self.emit_return();
+        #[cfg(feature = "debug_info")]
+        self.compiler.record_remaining_locals();
+        core::mem::replace(
+            &mut self.compiler.function,
+            Function::new(None, Rc::from("")),
+        )
+    }
+
+    // ------------------------------------------------------------------
+    // declarations and statements
+    // ------------------------------------------------------------------
+
+    fn declaration(&mut self) {
+        if self.matches(TokenKind::Class) {
+            self.class_decl();
+        } else if self.matches(TokenKind::Fun) {
+            self.fun_decl();
+        } else if self.matches(TokenKind::Var) {
+            self.var_decl();
+        } else {
+            self.statement();
+        }
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    pub(crate) fn statement(&mut self) {
+        if self.matches(TokenKind::Print) {
+            self.print_statement();
+        } else if self.matches(TokenKind::If) {
+            self.if_statement();
+        } else if self.matches(TokenKind::Return) {
+            self.return_statement();
+        } else if self.matches(TokenKind::While) {
+            self.while_statement();
+        } else if self.matches(TokenKind::Do) {
+            self.do_while_statement();
+        } else if self.matches(TokenKind::For) {
+            self.for_statement();
+        } else if self.matches(TokenKind::Try) {
+            self.try_statement();
+        } else if self.matches(TokenKind::Throw) {
+            self.throw_statement();
+        } else if self.matches(TokenKind::LBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn var_decl(&mut self) {
+        if self.matches(TokenKind::LBracket) {
+            self.destructuring_decl();
+            return;
+        }
+        let global = self.parse_variable("Expect variable name.");
+        if self.matches(TokenKind::Eq) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(
+            TokenKind::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+        self.define_variable(global);
+    }
+
+    /// `var [a, b] = xs;` — binds successive list elements to each name.
+    /// Indexing past the end of the list is the usual runtime range error
+    /// rather than filling with nil.
+    fn destructuring_decl(&mut self) {
+        let mut names = Vec::new();
+        loop {
+            self.consume(TokenKind::Ident, "Expect variable name.");
+            names.push(self.prev);
+            if !self.matches(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenKind::RBracket, "Expect ']' after variable names.");
+        self.consume(TokenKind::Eq, "Expect '=' after destructuring pattern.");
+        self.expression();
+        self.consume(
+            TokenKind::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+        // keep the list addressable while each element is read out
+        let rhs_slot = self.hidden_local();
+        for (i, token) in names.iter().enumerate() {
+            self.emit_op(OpCode::ReadLocal);
+            self.emit_byte(rhs_slot);
+            let idx = self.make_constant(Value::Float(i as f64));
+            self.emit_op(OpCode::Constant);
+            self.emit_byte(idx);
+            self.emit_op(OpCode::IndexGet);
+            if self.compiler.scope_depth == 0 {
+                let global = self.identifier_constant(*token);
+                self.emit_op(OpCode::DefineGlobal);
+                self.emit_byte(global);
+            } else {
+                self.add_local(token.data);
+                self.mark_initialized();
+            }
+        }
+        if self.compiler.scope_depth == 0 {
+            // globals popped their values; drop the list and its slot
+            self.emit_op(OpCode::Pop);
+            self.compiler.locals.pop();
+        }
+    }
+
+    fn fun_decl(&mut self) {
+        // `prev` is the `fun` keyword here, so its doc comment (if any) is
+        // the one written directly above the declaration
+        let doc = self.prev_doc.take();
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        self.function_with_doc(FunKind::Function, false, doc);
+        self.define_variable(global);
+    }
+
+    fn class_decl(&mut self) {
+        self.consume(TokenKind::Ident, "Expect class name.");
+        let name_token = self.prev;
+        let name_const = self.identifier_constant(name_token);
+        self.declare_variable();
+
+        self.emit_op(OpCode::Class);
+        self.emit_byte(name_const);
+        self.define_variable(name_const);
+
+        self.class_compilers.push(ClassCtx {
+            has_superclass: false,
+            in_static: false,
+        });
+
+        if self.matches(TokenKind::Less) {
+            self.consume(TokenKind::Ident, "Expect superclass name.");
+            self.variable(false);
+            if name_token.data == self.prev.data {
+                self.log_error("A class cannot inherit from itself.");
+            }
+            self.begin_scope();
+            self.add_local("super");
+            self.mark_initialized();
+            self.named_variable(name_token, false);
+            self.emit_op(OpCode::Inherit);
+            self.class_compilers.last_mut().unwrap().has_superclass = true;
+        }
+
+        self.named_variable(name_token, false);
+        self.consume(TokenKind::LBrace, "Expect '{' before class body.");
+        while !self.check(TokenKind::RBrace) && !self.check(TokenKind::Eof) {
+            self.method();
+        }
+        self.consume(TokenKind::RBrace, "Expect '}' after class body.");
+        self.emit_op(OpCode::Pop);
+
+        if self.class_compilers.last().unwrap().has_superclass {
+            self.end_scope();
+        }
+        self.class_compilers.pop();
+    }
+
+    fn method(&mut self) {
+        let is_static = self.matches(TokenKind::Static);
+        self.consume(TokenKind::Ident, "Expect method name.");
+        let name_const = self.identifier_constant(self.prev);
+        let kind = if is_static {
+            // statics have no receiver, so they compile like plain functions
+            FunKind::Function
+        } else if self.prev.data == "init" {
+            FunKind::Initializer
+        } else {
+            FunKind::Method
+        };
+        // a method body with no parameter list is a getter
+        let is_getter = self.check(TokenKind::LBrace);
+        if is_getter && kind == FunKind::Initializer {
+            self.log_error("An initializer cannot be a getter.");
+        }
+        if is_static {
+            self.class_compilers.last_mut().unwrap().in_static = true;
+        }
+        self.function(kind, is_getter);
+        if is_static {
+            self.class_compilers.last_mut().unwrap().in_static = false;
+        }
+        self.emit_op(if is_static {
+            OpCode::StaticMethod
+        } else {
+            OpCode::Method
+        });
+        self.emit_byte(name_const);
+    }
+
+    fn function(&mut self, kind: FunKind, is_getter: bool) {
+        self.function_with_doc(kind, is_getter, None);
+    }
+
+    fn function_with_doc(&mut self, kind: FunKind, is_getter: bool, doc: Option<&str>) {
+        let name = self.intern(self.prev.data);
+        let source = Rc::clone(&self.compiler.function.chunk.source);
+        let new = Box::new(Compiler::new(kind, Some(name), source, self.constant_pool.clone()));
+        let enclosing = core::mem::replace(&mut self.compiler, new);
+        self.compiler.enclosing = Some(enclosing);
+
+        self.compiler.function.doc = doc.map(Rc::from);
+        self.begin_scope();
+        if is_getter {
+            self.compiler.function.is_getter = true;
+        } else {
+            self.consume(TokenKind::LParen, "Expect '(' after function name.");
+            if !self.check(TokenKind::RParen) {
+                loop {
+                    if self.compiler.function.arg_count == u8::MAX {
+                        let current = self.current;
+                        self.log_error_at(current, "Cannot have more than 255 parameters.");
+                    }
+                    self.compiler.function.arg_count =
+                        self.compiler.function.arg_count.wrapping_add(1);
+                    if self.matches(TokenKind::Ellipsis) {
+                        self.compiler.function.is_variadic = true;
+                    }
+                    let param = self.parse_variable("Expect parameter name.");
+                    let param_name = self.intern(self.prev.data);
+                    self.compiler.function.params.push(param_name);
+                    self.define_variable(param);
+                    if self.compiler.function.is_variadic {
+                        // the rest parameter must be last
+                        break;
+                    }
+                    if !self.matches(TokenKind::Comma) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenKind::RParen, "Expect ')' after parameters.");
+        }
+        self.consume(TokenKind::LBrace, "Expect '{' before function body.");
+        self.block();
+
+        self.emit_return();
+        #[cfg(feature = "debug_info")]
+        self.compiler.record_remaining_locals();
+        let enclosing = self.compiler.enclosing.take().unwrap();
+        let done = core::mem::replace(&mut self.compiler, enclosing);
+        // body locals never pass through `end_scope`; parameters (the slots
+        // right after the callee) are exempt
+        for local in done.locals.iter().skip(1 + done.function.arg_count as usize) {
+            warn_unused(local);
+        }
+        let upvalues = done.upvalues;
+        let function = Rc::new(done.function);
+
+        let idx = self.make_constant(Value::Function(function));
+        self.emit_op(OpCode::Closure);
+        self.emit_byte(idx);
+        for upval in upvalues {
+            self.emit_byte(upval.is_local as u8);
+            self.emit_byte(upval.index);
+        }
+    }
+
+    fn block(&mut self) {
+        let enclosing_terminated = self.terminated;
+        self.terminated = false;
+        while !self.check(TokenKind::RBrace) && !self.check(TokenKind::Eof) {
+            if self.terminated {
+                self.dead_declaration();
+            } else {
+                self.declaration();
+            }
+        }
+        self.consume(TokenKind::RBrace, "Expect '}' after block.");
+        self.terminated = enclosing_terminated;
+    }
+
+    /// Parses a statement that can never execute, discarding whatever it
+    /// emits. Syntax errors are still reported as usual.
+    fn dead_declaration(&mut self) {
+        let data_len = self.chunk().data.len();
+        let constants_len = self.constant_pool.len();
+        let locals_len = self.compiler.locals.len();
+        self.declaration();
+        self.chunk().truncate(data_len);
+        self.constant_pool.truncate(constants_len);
+        let mut compiler = Some(&mut *self.compiler);
+        while let Some(c) = compiler {
+            c.function.chunk.constants.truncate(constants_len);
+            compiler = c.enclosing.as_deref_mut();
+        }
+        self.compiler.locals.truncate(locals_len);
+    }
+
+    /// Compiles a statement that only runs conditionally: a `return` inside
+    /// it must not mark the code that follows as dead.
+    fn conditional_statement(&mut self) {
+        let terminated = self.terminated;
+        self.statement();
+        self.terminated = terminated;
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(TokenKind::Semicolon, "Expect ';' after value.");
+        self.emit_op(OpCode::Print);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TokenKind::Semicolon, "Expect ';' after expression.");
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenKind::LParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenKind::RParen, "Expect ')' after condition.");
+
+        let then_jump = self.push_jump(OpCode::JumpFalseyPop);
+        self.conditional_statement();
+        let else_jump = self.push_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        if self.matches(TokenKind::Else) {
+            self.conditional_statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = {
+            let pos = self.chunk().data.len();
+            self.track_pos(pos)
+        };
+        self.consume(TokenKind::LParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RParen, "Expect ')' after condition.");
+
+        let exit_jump = self.push_jump(OpCode::JumpFalseyPop);
+        self.conditional_statement();
+        self.push_loop(loop_start);
+        self.patch_jump(exit_jump);
+    }
+
+    /// `do <statement> while (<cond>);` — the body always runs at least once.
+    fn do_while_statement(&mut self) {
+        let loop_start = {
+            let pos = self.chunk().data.len();
+            self.track_pos(pos)
+        };
+        self.conditional_statement();
+        self.consume(TokenKind::While, "Expect 'while' after do body.");
+        self.consume(TokenKind::LParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RParen, "Expect ')' after condition.");
+        self.consume(TokenKind::Semicolon, "Expect ';' after do-while condition.");
+
+        let exit_jump = self.push_jump(OpCode::JumpFalseyPop);
+        self.push_loop(loop_start);
+        self.patch_jump(exit_jump);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenKind::LParen, "Expect '(' after 'for'.");
+        if self.check(TokenKind::Ident) && self.peek_next().kind == TokenKind::In {
+            self.foreach_statement();
+            return;
+        }
+        if self.matches(TokenKind::Semicolon) {
+            // no initializer
+        } else if self.matches(TokenKind::Var) {
+            self.var_decl();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = {
+            let pos = self.chunk().data.len();
+            self.track_pos(pos)
+        };
+        let mut exit_jump = None;
+        if !self.matches(TokenKind::Semicolon) {
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.push_jump(OpCode::JumpFalseyPop));
+        }
+
+        if !self.matches(TokenKind::RParen) {
+            let body_jump = self.push_jump(OpCode::Jump);
+            let increment_start = {
+                let pos = self.chunk().data.len();
+                self.track_pos(pos)
+            };
+            self.expression();
+            self.emit_op(OpCode::Pop);
+            self.consume(TokenKind::RParen, "Expect ')' after for clauses.");
+            self.push_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.conditional_statement();
+        self.push_loop(loop_start);
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+        }
+        self.end_scope();
+    }
+
+    /// `for (x in xs) body` — desugars to an index-counter loop over a list.
+    /// The list and counter live in hidden locals; the loop variable is a
+    /// fresh local each iteration so closures capture distinct values.
+    /// Assumes the enclosing scope from `for_statement`.
+    fn foreach_statement(&mut self) {
+        self.consume(TokenKind::Ident, "Expect loop variable name.");
+        let name = self.prev;
+        self.consume(TokenKind::In, "Expect 'in' after loop variable.");
+        self.expression();
+        let list_slot = self.hidden_local();
+        self.emit_constant(Value::Float(0.0));
+        let idx_slot = self.hidden_local();
+        self.consume(TokenKind::RParen, "Expect ')' after foreach clauses.");
+
+        let loop_start = {
+            let pos = self.chunk().data.len();
+            self.track_pos(pos)
+        };
+        // idx < len(list)
+        self.emit_op(OpCode::ReadLocal);
+        self.emit_byte(idx_slot);
+        self.emit_op(OpCode::ReadLocal);
+        self.emit_byte(list_slot);
+        self.emit_op(OpCode::ListLen);
+        self.emit_op(OpCode::Less);
+        let exit_jump = self.push_jump(OpCode::JumpFalseyPop);
+
+        // x = list[idx], rebound every iteration
+        self.begin_scope();
+        self.emit_op(OpCode::ReadLocal);
+        self.emit_byte(list_slot);
+        self.emit_op(OpCode::ReadLocal);
+        self.emit_byte(idx_slot);
+        self.emit_op(OpCode::IndexGet);
+        self.add_local(name.data);
+        self.mark_initialized();
+        self.conditional_statement();
+        self.end_scope();
+
+        // idx = idx + 1
+        self.emit_op(OpCode::ReadLocal);
+        self.emit_byte(idx_slot);
+        self.emit_constant(Value::Float(1.0));
+        self.emit_op(OpCode::Add);
+        self.emit_op(OpCode::WriteLocal);
+        self.emit_byte(idx_slot);
+        self.emit_op(OpCode::Pop);
+
+        self.push_loop(loop_start);
+        self.patch_jump(exit_jump);
+        self.end_scope();
+    }
+
+    /// Claims the value on top of the stack as an unnamed local, returning
+    /// its slot. The empty name can never collide with a user identifier.
+    fn hidden_local(&mut self) -> u8 {
+        self.add_local("");
+        self.mark_initialized();
+        (self.compiler.locals.len() - 1) as u8
+    }
+
+    /// `try { ... } catch (e) { ... }` — a runtime error in the try block
+    /// unwinds to the catch block with the error bound to `e`.
+    fn try_statement(&mut self) {
+        let handler = self.push_jump(OpCode::PushHandler);
+        self.consume(TokenKind::LBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.emit_op(OpCode::PopHandler);
+        let end_jump = self.push_jump(OpCode::Jump);
+
+        self.patch_jump(handler);
+        self.consume(TokenKind::Catch, "Expect 'catch' after try block.");
+        self.consume(TokenKind::LParen, "Expect '(' after 'catch'.");
+        self.begin_scope();
+        self.consume(TokenKind::Ident, "Expect error variable name.");
+        // the unwinder leaves the caught error on the stack; claim that slot
+        // as the error variable
+        self.declare_variable();
+        self.mark_initialized();
+        self.consume(TokenKind::RParen, "Expect ')' after error variable.");
+        self.consume(TokenKind::LBrace, "Expect '{' before catch body.");
+        self.block();
+        self.end_scope();
+        self.patch_jump(end_jump);
+    }
+
+    /// `throw <expr>;` — raises the value as a catchable error.
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TokenKind::Semicolon, "Expect ';' after thrown value.");
+        self.emit_op(OpCode::Throw);
+    }
+
+    fn return_statement(&mut self) {
+        if self.compiler.kind == FunKind::Script {
+            self.log_error("Cannot return from top-level code.");
+        }
+        if self.matches(TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.compiler.kind == FunKind::Initializer {
+                self.log_error("Cannot return a value from an initializer.");
+            }
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after return value.");
+            self.emit_op(OpCode::Return);
+        }
+        self.terminated = true;
+    }
+
+    // ------------------------------------------------------------------
+    // scopes and variables
+    // ------------------------------------------------------------------
+
+    fn begin_scope(&mut self) {
+        self.compiler.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.compiler.scope_depth -= 1;
+        // runs of plain pops collapse into one StackSub; captured locals
+        // still need their own CloseUpval at the right stack position
+        let mut pending = 0;
+        while let Some(local) = self.compiler.locals.last() {
+            if local.depth <= self.compiler.scope_depth {
+                break;
+            }
+            let local = self.compiler.locals.pop().unwrap();
+            warn_unused(&local);
+            #[cfg(feature = "debug_info")]
+            {
+                let slot = self.compiler.locals.len();
+                self.compiler.record_local(local.name, slot, local.start_offset);
+            }
+            if local.captured {
+                self.flush_pops(pending);
+                pending = 0;
+                self.emit_op(OpCode::CloseUpval);
+            } else {
+                pending += 1;
+            }
+        }
+        self.flush_pops(pending);
+    }
+
+    fn flush_pops(&mut self, count: usize) {
+        match count {
+            0 => {}
+            1 => self.emit_op(OpCode::Pop),
+            n => {
+                self.emit_op(OpCode::StackSub);
+                self.emit_byte(n as u8);
+            }
+        }
+    }
+
+    fn parse_variable(&mut self, msg: &str) -> u8 {
+        self.consume(TokenKind::Ident, msg);
+        self.declare_variable();
+        if self.compiler.scope_depth > 0 {
+            return 0;
+        }
+        self.identifier_constant(self.prev)
+    }
+
+    fn declare_variable(&mut self) {
+        if self.compiler.scope_depth == 0 {
+            return;
+        }
+        let name = self.prev.data;
+        let mut duplicate = false;
+        for local in self.compiler.locals.iter().rev() {
+            if local.depth != -1 && local.depth < self.compiler.scope_depth {
+                break;
+            }
+            if local.name == name {
+                duplicate = true;
+                break;
+            }
+        }
+        if duplicate {
+            self.log_error("Variable with this name already declared in this scope.");
+        }
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: &'src str) {
+        if self.compiler.locals.len() >= MAX_LOCALS {
+            self.log_error("Too many local variables in function.");
+            return;
+        }
+        self.compiler.locals.push(Local {
+            name,
+            depth: -1,
+            captured: false,
+            used: false,
+            line: self.prev.line,
+            #[cfg(feature = "debug_info")]
+            start_offset: 0,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.compiler.scope_depth == 0 {
+            return;
+        }
+        #[cfg(feature = "debug_info")]
+        let live_from = self.compiler.function.chunk.data.len();
+        if let Some(local) = self.compiler.locals.last_mut() {
+            local.depth = self.compiler.scope_depth;
+            #[cfg(feature = "debug_info")]
+            {
+                local.start_offset = live_from;
+            }
+        }
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.compiler.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_op(OpCode::DefineGlobal);
+        self.emit_byte(global);
+    }
+
+    fn named_variable(&mut self, token: Token<'src>, can_assign: bool) {
+        let Some((read_op, write_op, arg)) = self.resolve_variable(token) else {
+            return;
+        };
+
+        if can_assign && self.matches(TokenKind::Eq) {
+            self.expression();
+            self.emit_op(write_op);
+        } else if matches!(
+            self.current.kind,
+            TokenKind::PlusPlus | TokenKind::MinusMinus
+        ) {
+            self.advance();
+            self.emit_incdec(self.prev.kind, read_op, write_op, arg, false);
+            return;
+        } else {
+            if read_op == OpCode::ReadLocal {
+                self.compiler.locals[arg as usize].used = true;
+            }
+            self.emit_op(read_op);
+        }
+        self.emit_byte(arg);
+    }
+
+    fn resolve_variable(&mut self, token: Token<'src>) -> Option<(OpCode, OpCode, u8)> {
+        let name = token.data;
+        match resolve_local(&self.compiler, name) {
+            Err(msg) => {
+                self.log_error(&msg);
+                None
+            }
+            Ok(Some(slot)) => Some((OpCode::ReadLocal, OpCode::WriteLocal, slot)),
+            Ok(None) => match resolve_upvalue(&mut self.compiler, name) {
+                Err(msg) => {
+                    self.log_error(&msg);
+                    None
+                }
+                Ok(Some(idx)) => Some((OpCode::ReadUpval, OpCode::WriteUpval, idx)),
+                Ok(None) => {
+                    let idx = self.identifier_constant(token);
+                    Some((OpCode::ReadGlobal, OpCode::WriteGlobal, idx))
+                }
+            },
+        }
+    }
+
+    /// Read-modify-write for `++`/`--` on a variable. Prefix leaves the
+    /// updated value on the stack; postfix keeps a copy of the original
+    /// beneath the write and drops the new value.
+    fn emit_incdec(
+        &mut self,
+        op_kind: TokenKind,
+        read_op: OpCode,
+        write_op: OpCode,
+        arg: u8,
+        prefix: bool,
+    ) {
+        let op = if op_kind == TokenKind::PlusPlus {
+            OpCode::Add
+        } else {
+            OpCode::Sub
+        };
+        if read_op == OpCode::ReadLocal {
+            self.compiler.locals[arg as usize].used = true;
+        }
+        self.emit_op(read_op);
+        self.emit_byte(arg);
+        if !prefix {
+            self.emit_op(OpCode::Dup);
+        }
+        self.emit_constant(Value::Float(1.0));
+        self.emit_op(op);
+        self.emit_op(write_op);
+        self.emit_byte(arg);
+        if !prefix {
+            self.emit_op(OpCode::Pop);
+        }
+    }
+
+    /// Read-modify-write for `++`/`--` on a field, with the receiver already
+    /// on the stack. Postfix recovers the original value by undoing the step
+    /// after the write, which avoids extra stack-shuffling opcodes.
+    fn emit_property_incdec(&mut self, op_kind: TokenKind, prop: u8, prefix: bool) {
+        let (op, undo) = if op_kind == TokenKind::PlusPlus {
+            (OpCode::Add, OpCode::Sub)
+        } else {
+            (OpCode::Sub, OpCode::Add)
+        };
+        self.emit_op(OpCode::Dup);
+        self.emit_op(OpCode::ReadProperty);
+        self.emit_byte(prop);
+        self.emit_constant(Value::Float(1.0));
+        self.emit_op(op);
+        self.emit_op(OpCode::WriteProperty);
+        self.emit_byte(prop);
+        if !prefix {
+            self.emit_constant(Value::Float(1.0));
+            self.emit_op(undo);
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // expressions
+    // ------------------------------------------------------------------
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let Some(prefix) = get_rule(self.prev.kind).prefix else {
+            self.log_error("Expect expression.");
+            return;
+        };
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix(self, can_assign);
+
+        while precedence <= get_rule(self.current.kind).precedence {
+            self.advance();
+            let infix = get_rule(self.prev.kind).infix.unwrap();
+            infix(self, can_assign);
+        }
+
+        if can_assign && self.matches(TokenKind::Eq) {
+            self.log_error("Invalid assignment target.");
+        }
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        let value: f64 = self.prev.data.parse().unwrap();
+        // the commonest literals get immediate opcodes instead of a
+        // constant slot and operand byte
+        if value == 0.0 {
+            self.emit_op(OpCode::Zero);
+        } else if value == 1.0 {
+            self.emit_op(OpCode::One);
+        } else {
+            self.emit_constant(Value::Float(value));
+        }
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        if self.prev.kind == TokenKind::RawString {
+            // content between the quotes, backslashes and all
+            let raw = &self.prev.data[2..self.prev.data.len() - 1];
+            let interned = self.intern(raw);
+            self.emit_constant(Value::String(interned));
+            return;
+        }
+        let raw = &self.prev.data[1..self.prev.data.len() - 1];
+        match unescape(raw) {
+            Ok(text) => {
+                let interned = self.intern(&text);
+                self.emit_constant(Value::String(interned));
+            }
+            Err(msg) => self.log_error(msg),
+        }
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.prev.kind {
+            TokenKind::Nil => self.emit_op(OpCode::Nil),
+            TokenKind::True => self.emit_op(OpCode::True),
+            TokenKind::False => self.emit_op(OpCode::False),
+            _ => unreachable!(),
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TokenKind::RParen, "Expect ')' after expression.");
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let kind = self.prev.kind;
+        // fold `-` applied directly to a numeric literal into a single
+        // negative constant instead of a push-then-Negate pair. This is
+        // safe to do before the usual operand parse: the only infixes that
+        // bind tighter than unary (call, property, index) reject numbers
+        // regardless of sign, so no expression distinguishes `-(5)...`
+        // from `(-5)...`
+        if kind == TokenKind::Minus && self.matches(TokenKind::Number) {
+            let value: f64 = self.prev.data.parse().unwrap();
+            self.emit_constant(Value::Float(-value));
+            return;
+        }
+        self.parse_precedence(Precedence::Unary);
+        match kind {
+            TokenKind::Minus => self.emit_op(OpCode::Negate),
+            TokenKind::Bang => self.emit_op(OpCode::Not),
+            TokenKind::Tilde => self.emit_op(OpCode::BitNot),
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let kind = self.prev.kind;
+        let rule = get_rule(kind);
+        self.parse_precedence(rule.precedence.next());
+        match kind {
+            TokenKind::Plus => self.emit_op(OpCode::Add),
+            TokenKind::Minus => self.emit_op(OpCode::Sub),
+            TokenKind::Star => self.emit_op(OpCode::Mul),
+            TokenKind::Slash => self.emit_op(OpCode::Div),
+            TokenKind::EqEq => self.emit_op(OpCode::Equal),
+            TokenKind::BangEq => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            TokenKind::Greater => self.emit_op(OpCode::Greater),
+            TokenKind::GreaterEq => {
+                self.emit_op(OpCode::Less);
+                self.emit_op(OpCode::Not);
+            }
+            TokenKind::Less => self.emit_op(OpCode::Less),
+            TokenKind::LessEq => {
+                self.emit_op(OpCode::Greater);
+                self.emit_op(OpCode::Not);
+            }
+            TokenKind::Amp => self.emit_op(OpCode::BitAnd),
+            TokenKind::Pipe => self.emit_op(OpCode::BitOr),
+            TokenKind::Caret => self.emit_op(OpCode::BitXor),
+            TokenKind::Shl => self.emit_op(OpCode::Shl),
+            TokenKind::Shr => self.emit_op(OpCode::Shr),
+            _ => unreachable!(),
+        }
+    }
+
+    /// `do { ... }` as an expression: statements run in their own scope and
+    /// the trailing expression (no semicolon) becomes the block's value.
+    ///
+    /// Compiled as an immediately-invoked zero-argument closure. That keeps
+    /// local-slot numbering honest when the block appears mid-expression
+    /// (e.g. as a call argument, where temporaries already sit on the
+    /// stack), at the cost of a frame per evaluation.
+    fn do_expression(&mut self, _can_assign: bool) {
+        self.consume(TokenKind::LBrace, "Expect '{' after 'do'.");
+        let name = self.intern("do block");
+        let source = Rc::clone(&self.compiler.function.chunk.source);
+        let new = Box::new(Compiler::new(
+            FunKind::Function,
+            Some(name),
+            source,
+            self.constant_pool.clone(),
+        ));
+        let enclosing = core::mem::replace(&mut self.compiler, new);
+        self.compiler.enclosing = Some(enclosing);
+        self.begin_scope();
+
+        loop {
+            if self.check(TokenKind::RBrace) || self.check(TokenKind::Eof) {
+                // empty block or one ending in a statement: the value is nil
+                break;
+            }
+            if matches!(
+                self.current.kind,
+                TokenKind::Var
+                    | TokenKind::Fun
+                    | TokenKind::Class
+                    | TokenKind::Print
+                    | TokenKind::If
+                    | TokenKind::While
+                    | TokenKind::Do
+                    | TokenKind::For
+                    | TokenKind::Return
+                    | TokenKind::Try
+                    | TokenKind::Throw
+                    | TokenKind::LBrace
+            ) {
+                self.declaration();
+                continue;
+            }
+            self.expression();
+            if self.matches(TokenKind::Semicolon) {
+                self.emit_op(OpCode::Pop);
+            } else {
+                // trailing expression: its value is the block's result
+                self.emit_op(OpCode::Return);
+                break;
+            }
+        }
+        self.consume(TokenKind::RBrace, "Expect '}' after do block.");
+
+        self.emit_return();
+        #[cfg(feature = "debug_info")]
+        self.compiler.record_remaining_locals();
+        let enclosing = self.compiler.enclosing.take().unwrap();
+        let done = core::mem::replace(&mut self.compiler, enclosing);
+        for local in done.locals.iter().skip(1) {
+            warn_unused(local);
+        }
+        let upvalues = done.upvalues;
+        let function = Rc::new(done.function);
+
+        let idx = self.make_constant(Value::Function(function));
+        self.emit_op(OpCode::Closure);
+        self.emit_byte(idx);
+        for upval in upvalues {
+            self.emit_byte(upval.is_local as u8);
+            self.emit_byte(upval.index);
+        }
+        self.emit_op(OpCode::Call);
+        self.emit_byte(0);
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.push_jump(OpCode::JumpFalseyOrPop);
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let end_jump = self.push_jump(OpCode::JumpTruthyOrPop);
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    /// `++x` / `--x`. The operand must be a variable or a property chain
+    /// ending in a field; anything else is rejected at compile time.
+    fn prefix_incdec(&mut self, _can_assign: bool) {
+        let op_kind = self.prev.kind;
+        self.consume(TokenKind::Ident, "Expect variable name after prefix operator.");
+        let name = self.prev;
+        if !self.check(TokenKind::Dot) {
+            let Some((read_op, write_op, arg)) = self.resolve_variable(name) else {
+                return;
+            };
+            self.emit_incdec(op_kind, read_op, write_op, arg, true);
+            return;
+        }
+        // property target: plain reads down the chain, then modify the field
+        self.named_variable(name, false);
+        while self.matches(TokenKind::Dot) {
+            self.consume(TokenKind::Ident, "Expect property name after '.'.");
+            let prop = self.identifier_constant(self.prev);
+            if self.check(TokenKind::Dot) {
+                self.emit_op(OpCode::ReadProperty);
+                self.emit_byte(prop);
+            } else {
+                self.emit_property_incdec(op_kind, prop, true);
+            }
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        self.named_variable(self.prev, can_assign);
+    }
+
+    /// `print` in expression position reads the `print` native, so the
+    /// keyword statement coexists with first-class use.
+    fn print_value(&mut self, _can_assign: bool) {
+        let name = self.intern("print");
+        let idx = self.make_constant(Value::String(name));
+        self.emit_op(OpCode::ReadGlobal);
+        self.emit_byte(idx);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.emit_op(OpCode::Call);
+        self.emit_byte(arg_count);
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenKind::Ident, "Expect property name after '.'.");
+        let name_const = self.identifier_constant(self.prev);
+        if can_assign && self.matches(TokenKind::Eq) {
+            self.expression();
+            self.emit_op(OpCode::WriteProperty);
+        } else if self.matches(TokenKind::LParen) {
+            // immediate call: skip the bound-method allocation
+            let arg_count = self.argument_list();
+            self.emit_op(OpCode::Invoke);
+            self.emit_byte(name_const);
+            self.emit_byte(arg_count);
+            return;
+        } else if matches!(
+            self.current.kind,
+            TokenKind::PlusPlus | TokenKind::MinusMinus
+        ) {
+            self.advance();
+            self.emit_property_incdec(self.prev.kind, name_const, false);
+            return;
+        } else {
+            self.emit_op(OpCode::ReadProperty);
+        }
+        self.emit_byte(name_const);
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let mut count: u8 = 0;
+        if !self.check(TokenKind::RBracket) {
+            loop {
+                self.expression();
+                if count == u8::MAX {
+                    self.log_error("Cannot have more than 255 elements in a list literal.");
+                }
+                count = count.wrapping_add(1);
+                if !self.matches(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RBracket, "Expect ']' after list elements.");
+        self.emit_op(OpCode::List);
+        self.emit_byte(count);
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenKind::RBracket, "Expect ']' after index.");
+        if can_assign && self.matches(TokenKind::Eq) {
+            self.expression();
+            self.emit_op(OpCode::IndexSet);
+        } else {
+            self.emit_op(OpCode::IndexGet);
+        }
+    }
+
+    fn this_(&mut self, _can_assign: bool) {
+        match self.class_compilers.last() {
+            None => {
+                self.log_error("Cannot use 'this' outside of a class.");
+                return;
+            }
+            Some(ctx) if ctx.in_static => {
+                self.log_error("Cannot use 'this' in a static method.");
+                return;
+            }
+            Some(_) => {}
+        }
+        self.variable(false);
+    }
+
+    fn super_(&mut self, _can_assign: bool) {
+        match self.class_compilers.last() {
+            None => self.log_error("Cannot use 'super' outside of a class."),
+            Some(ctx) if !ctx.has_superclass => {
+                self.log_error("Cannot use 'super' in a class with no superclass.");
+            }
+            Some(_) => {}
+        }
+        self.consume(TokenKind::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenKind::Ident, "Expect superclass method name.");
+        let name_const = self.identifier_constant(self.prev);
+        self.named_variable(Token::new(TokenKind::This, "this", self.prev.line), false);
+        if self.matches(TokenKind::LParen) {
+            // immediate call: skip the bound-method allocation
+            let arg_count = self.argument_list();
+            self.named_variable(Token::new(TokenKind::Super, "super", self.prev.line), false);
+            self.emit_op(OpCode::SuperInvoke);
+            self.emit_byte(name_const);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable(Token::new(TokenKind::Super, "super", self.prev.line), false);
+            self.emit_op(OpCode::Super);
+            self.emit_byte(name_const);
+        }
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: u8 = 0;
+        if !self.check(TokenKind::RParen) {
+            loop {
+                self.expression();
+                if arg_count == u8::MAX {
+                    self.log_error("Cannot have more than 255 arguments.");
+                }
+                arg_count = arg_count.wrapping_add(1);
+                if !self.matches(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RParen, "Expect ')' after arguments.");
+        arg_count
+    }
+}
+
+/// Decodes backslash escapes in a string literal body.
+fn unescape(raw: &str) -> Result<String, &'static str> {
+    if !raw.contains('\\') {
+        return Ok(raw.to_string());
+    }
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err("Expect '{' after '\\u'.");
+                }
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    return Err("Unterminated '\\u{' escape.");
+                };
+                let digits = &rest[..end];
+                let scalar = u32::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(|_| !digits.is_empty() && digits.len() <= 6)
+                    .ok_or("Expect 1 to 6 hex digits in '\\u{' escape.")?;
+                let Some(c) = char::from_u32(scalar) else {
+                    return Err("Invalid Unicode scalar in '\\u{' escape.");
+                };
+                out.push(c);
+                for _ in 0..=end {
+                    chars.next();
+                }
+            }
+            _ => return Err("Invalid escape sequence."),
+        }
+    }
+    Ok(out)
+}
+
+fn get_rule<'src, 'vm>(kind: TokenKind) -> ParseRule<'src, 'vm> {
+    let (prefix, infix, precedence): (
+        Option<ParseFn<'src, 'vm>>,
+        Option<ParseFn<'src, 'vm>>,
+        Precedence,
+    ) = match kind {
+        TokenKind::LParen => (
+            Some(Parser::grouping),
+            Some(Parser::call),
+            Precedence::Call,
+        ),
+        TokenKind::Dot => (None, Some(Parser::dot), Precedence::Call),
+        TokenKind::LBracket => (Some(Parser::list), Some(Parser::index), Precedence::Call),
+        TokenKind::Minus => (Some(Parser::unary), Some(Parser::binary), Precedence::Term),
+        TokenKind::MinusMinus => (Some(Parser::prefix_incdec), None, Precedence::None),
+        TokenKind::Plus => (None, Some(Parser::binary), Precedence::Term),
+        TokenKind::PlusPlus => (Some(Parser::prefix_incdec), None, Precedence::None),
+        TokenKind::Slash | TokenKind::Star => (None, Some(Parser::binary), Precedence::Factor),
+        TokenKind::Bang => (Some(Parser::unary), None, Precedence::None),
+        TokenKind::BangEq | TokenKind::EqEq => {
+            (None, Some(Parser::binary), Precedence::Equality)
+        }
+        TokenKind::Greater | TokenKind::GreaterEq | TokenKind::Less | TokenKind::LessEq => {
+            (None, Some(Parser::binary), Precedence::Comparison)
+        }
+        TokenKind::Amp => (None, Some(Parser::binary), Precedence::BitAnd),
+        TokenKind::Pipe => (None, Some(Parser::binary), Precedence::BitOr),
+        TokenKind::Caret => (None, Some(Parser::binary), Precedence::BitXor),
+        TokenKind::Shl | TokenKind::Shr => (None, Some(Parser::binary), Precedence::Shift),
+        TokenKind::Tilde => (Some(Parser::unary), None, Precedence::None),
+        TokenKind::Ident => (Some(Parser::variable), None, Precedence::None),
+        TokenKind::String => (Some(Parser::string), None, Precedence::None),
+        TokenKind::Number => (Some(Parser::number), None, Precedence::None),
+        TokenKind::And => (None, Some(Parser::and), Precedence::And),
+        TokenKind::Or => (None, Some(Parser::or), Precedence::Or),
+        TokenKind::Nil | TokenKind::True | TokenKind::False => {
+            (Some(Parser::literal), None, Precedence::None)
+        }
+        TokenKind::This => (Some(Parser::this_), None, Precedence::None),
+        TokenKind::Do => (Some(Parser::do_expression), None, Precedence::None),
+        TokenKind::Super => (Some(Parser::super_), None, Precedence::None),
+        TokenKind::Print => (Some(Parser::print_value), None, Precedence::None),
+        TokenKind::RawString => (Some(Parser::string), None, Precedence::None),
+        _ => (None, None, Precedence::None),
+    };
+    ParseRule {
+        prefix,
+        infix,
+        precedence,
+    }
+}
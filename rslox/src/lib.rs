@@ -0,0 +1,2239 @@
+//! rslox: a bytecode VM for the Lox language.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod chunk;
+pub mod compiler;
+pub mod scanner;
+pub mod table;
+pub mod value;
+pub mod vm;
+
+use alloc::string::String;
+
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+pub use vm::{InterpretError, VM};
+
+/// Installs the global tracing subscriber. Safe to call more than once; later
+/// calls are ignored.
+#[cfg(feature = "std")]
+pub fn init_tracing(level: tracing::Level) {
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .without_time()
+        .with_writer(std::io::stderr)
+        .try_init();
+}
+
+/// Renders the token stream of `source`, one `kind data @ line` entry per
+/// row, for the `tokens` CLI subcommand.
+pub fn token_dump(source: &str) -> String {
+    use core::fmt::Write;
+
+    use scanner::{Scanner, TokenKind};
+
+    let mut scanner = Scanner::new(source);
+    let mut out = String::new();
+    loop {
+        let token = scanner.next_token();
+        let _ = writeln!(out, "{:4} {} {:?}", token.line, token.kind, token.data);
+        if token.kind == TokenKind::Eof {
+            return out;
+        }
+    }
+}
+
+/// Compiles and runs every `.lox` file in `dir`, returning per-file
+/// `(name, compile time, run time)` rows sorted by path. Script output is
+/// discarded so the timings reflect execution alone; files that fail to
+/// compile or run still contribute their measured times.
+#[cfg(feature = "std")]
+pub fn run_benchmarks(dir: impl AsRef<Path>) -> Vec<(String, Duration, Duration)> {
+    let dir = dir.as_ref();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    files.sort();
+
+    let mut results = Vec::with_capacity(files.len());
+    for file in files {
+        let source = read_file(&file);
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        let start = Instant::now();
+        let compiled = vm.compile(&source);
+        let compile_time = start.elapsed();
+        let start = Instant::now();
+        if let Ok(function) = compiled {
+            let _ = vm.execute(function);
+        }
+        let run_time = start.elapsed();
+        results.push((file.display().to_string(), compile_time, run_time));
+    }
+    results
+}
+
+#[cfg(feature = "std")]
+pub fn read_file(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("could not read {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use crate::{InterpretError, VM};
+
+    /// `Write` target shared with the VM so tests can inspect printed output.
+    #[derive(Clone, Default)]
+    pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Capture {
+        pub fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `source` on a fresh VM, returning the interpret result and
+    /// everything it printed.
+    pub fn run(source: &str) -> (Result<(), InterpretError>, String) {
+        let mut vm = VM::new();
+        let capture = Capture::default();
+        vm.set_output(Box::new(capture.clone()));
+        let result = vm.interpret(source);
+        (result, capture.contents())
+    }
+
+    /// Runs `f` with a tracing subscriber that records emitted diagnostics,
+    /// returning the captured log text.
+    pub fn capture_logs(f: impl FnOnce()) -> String {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for LogCapture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogCapture {
+            type Writer = LogCapture;
+
+            fn make_writer(&'a self) -> Self::Writer {
+                self.clone()
+            }
+        }
+
+        let capture = LogCapture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .without_time()
+            .with_writer(capture.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let bytes = capture.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[track_caller]
+    pub fn expect_printed(source: &str, expected: &str) {
+        let (result, printed) = run(source);
+        if let Err(e) = result {
+            panic!("expected success, got {e:?}\noutput so far:\n{printed}");
+        }
+        assert_eq!(printed, expected);
+    }
+
+    #[track_caller]
+    pub fn expect_compile_error(source: &str, needle: &str) {
+        let (result, _) = run(source);
+        match result {
+            Err(InterpretError::CompileError(msg)) => {
+                assert!(
+                    msg.contains(needle),
+                    "compile error {msg:?} does not contain {needle:?}"
+                );
+            }
+            other => panic!("expected compile error containing {needle:?}, got {other:?}"),
+        }
+    }
+
+    #[track_caller]
+    pub fn expect_runtime_error(source: &str, needle: &str) {
+        let (result, _) = run(source);
+        match result {
+            Err(InterpretError::RuntimeError { msg, .. }) => {
+                assert!(
+                    msg.contains(needle),
+                    "runtime error {msg:?} does not contain {needle:?}"
+                );
+            }
+            other => panic!("expected runtime error containing {needle:?}, got {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::*;
+
+    mod print {
+        use super::*;
+
+        #[test]
+        fn literals() {
+            expect_printed("print 123;", "123\n");
+            expect_printed("print 1.5;", "1.5\n");
+            expect_printed("print true;", "true\n");
+            expect_printed("print nil;", "nil\n");
+            expect_printed("print \"str\";", "str\n");
+        }
+
+        #[test]
+        fn missing_argument() {
+            expect_compile_error("print;", "Expect expression.");
+        }
+
+        #[test]
+        fn print_is_first_class() {
+            expect_printed("var p = print; p(42);", "42\n");
+            expect_printed(
+                "fun twice(f, x) { f(x); f(x); } twice(print, \"hi\");",
+                "hi\nhi\n",
+            );
+        }
+    }
+
+    mod expressions {
+        use super::*;
+
+        #[test]
+        fn arithmetic() {
+            expect_printed("print 1 + 2 * 3 - 4 / 2;", "5\n");
+            expect_printed("print -(1 + 2);", "-3\n");
+        }
+
+        #[test]
+        fn comparison_and_equality() {
+            expect_printed("print 1 < 2;", "true\n");
+            expect_printed("print 2 <= 2;", "true\n");
+            expect_printed("print 1 == 1;", "true\n");
+            expect_printed("print 1 != 2;", "true\n");
+            expect_printed("print \"a\" == \"a\";", "true\n");
+            expect_printed("print nil == false;", "false\n");
+        }
+
+        #[test]
+        fn nan_equality() {
+            expect_printed("var nan = 0 / 0; print nan == nan;", "false\n");
+        }
+
+        #[test]
+        fn string_concat() {
+            expect_printed("print \"foo\" + \"bar\";", "foobar\n");
+        }
+
+        #[test]
+        fn add_type_error() {
+            expect_runtime_error(
+                "print 1 + \"a\";",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+
+        #[test]
+        fn string_repeat() {
+            expect_printed("print \"ab\" * 3;", "ababab\n");
+            expect_printed("print 3 * \"ab\";", "ababab\n");
+            expect_printed("print \"x\" * 0 + \"|\";", "|\n");
+        }
+
+        #[test]
+        fn string_repeat_rejects_fractional_count() {
+            expect_runtime_error(
+                "print \"x\" * 2.5;",
+                "String repeat count must be a non-negative whole number.",
+            );
+            expect_runtime_error(
+                "print \"x\" * -1;",
+                "String repeat count must be a non-negative whole number.",
+            );
+        }
+
+        #[test]
+        fn logical_operators() {
+            expect_printed("print 1 and 2;", "2\n");
+            expect_printed("print nil and 2;", "nil\n");
+            expect_printed("print nil or 2;", "2\n");
+            expect_printed("print 1 or 2;", "1\n");
+        }
+    }
+
+    mod variables {
+        use super::*;
+
+        #[test]
+        fn globals() {
+            expect_printed("var a = 1; a = a + 1; print a;", "2\n");
+        }
+
+        #[test]
+        fn undefined_global() {
+            expect_runtime_error("print missing;", "Undefined variable 'missing'.");
+        }
+
+        #[test]
+        fn locals_shadow() {
+            expect_printed(
+                "var a = 1; { var a = 2; print a; } print a;",
+                "2\n1\n",
+            );
+        }
+
+        #[test]
+        fn use_local_in_initializer() {
+            expect_compile_error(
+                "{ var a = 1; { var a = a; } }",
+                "Cannot read local variable 'a' in its own initializer.",
+            );
+        }
+
+        #[test]
+        fn duplicate_local() {
+            expect_compile_error(
+                "{ var a = 1; var a = 2; }",
+                "Variable with this name already declared in this scope.",
+            );
+        }
+
+        #[test]
+        fn cached_global_sees_redefinition() {
+            // the read inside `get` caches its globals slot on the first
+            // call; redefining `g` must not serve a stale value
+            expect_printed(
+                r#"
+                var g = 1;
+                fun get() { return g; }
+                print get();
+                var g = 2;
+                print get();
+                "#,
+                "1\n2\n",
+            );
+        }
+
+        #[test]
+        fn cached_global_survives_table_growth() {
+            // enough later definitions to force the globals table to rehash,
+            // which moves the cached slot out from under the read
+            let mut source = String::from(
+                "var g = 1;\nfun get() { return g; }\nprint get();\n",
+            );
+            for i in 0..60 {
+                source.push_str(&format!("var fill{i} = {i};\n"));
+            }
+            source.push_str("print get();\ng = 2;\nprint get();\n");
+            expect_printed(&source, "1\n1\n2\n");
+        }
+    }
+
+    mod control_flow {
+        use super::*;
+
+        #[test]
+        fn if_else() {
+            expect_printed("if (true) print 1; else print 2;", "1\n");
+            expect_printed("if (false) print 1; else print 2;", "2\n");
+        }
+
+        #[test]
+        fn while_loop() {
+            expect_printed(
+                "var i = 0; while (i < 3) { print i; i = i + 1; }",
+                "0\n1\n2\n",
+            );
+        }
+
+        #[test]
+        fn do_while_runs_body_once() {
+            expect_printed("do { print \"once\"; } while (false);", "once\n");
+        }
+
+        #[test]
+        fn do_while_loops_until_falsey() {
+            expect_printed(
+                "var i = 0; do { print i; i = i + 1; } while (i < 3);",
+                "0\n1\n2\n",
+            );
+        }
+
+        #[test]
+        fn do_while_body_scope() {
+            expect_printed(
+                "var i = 0; do { var local = i * 2; print local; i = i + 1; } while (i < 2);",
+                "0\n2\n",
+            );
+        }
+
+        #[test]
+        fn for_loop() {
+            expect_printed(
+                "for (var i = 0; i < 3; i = i + 1) print i;",
+                "0\n1\n2\n",
+            );
+        }
+    }
+
+    mod functions {
+        use super::*;
+
+        #[test]
+        fn call_and_return() {
+            expect_printed(
+                "fun add(a, b) { return a + b; } print add(1, 2);",
+                "3\n",
+            );
+        }
+
+        #[test]
+        fn recursion() {
+            expect_printed(
+                "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);",
+                "55\n",
+            );
+        }
+
+        #[test]
+        fn missing_arguments() {
+            expect_runtime_error(
+                "fun f(a, b) {} f(1);",
+                "Function(f) expects 2 args, got 1 (missing 'b').",
+            );
+        }
+
+        #[test]
+        fn call_nonfunction() {
+            expect_runtime_error("var x = 1; x();", "Can only call functions and classes.");
+        }
+
+        #[test]
+        fn top_level_return() {
+            expect_compile_error("return 1;", "Cannot return from top-level code.");
+        }
+
+        #[test]
+        fn stack_overflow() {
+            expect_runtime_error("fun f() { f(); } f();", "Stack overflow.");
+        }
+    }
+
+    mod bench {
+        #[test]
+        fn harness_times_one_file() {
+            let dir = std::env::temp_dir().join("rslox_bench_test");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("loop.lox"),
+                "var t = 0; for (var i = 0; i < 1000; i = i + 1) { t = t + i; }",
+            )
+            .unwrap();
+            let results = crate::run_benchmarks(&dir);
+            let row = results
+                .iter()
+                .find(|(name, ..)| name.ends_with("loop.lox"))
+                .expect("benchmark file was timed");
+            assert!(!row.2.is_zero(), "run duration: {:?}", row.2);
+        }
+    }
+
+    mod tokens {
+        use crate::token_dump;
+
+        #[test]
+        fn dump_lists_kinds_in_order() {
+            let text = token_dump("var x = 1; // trailing\nprint x;");
+            let kinds: Vec<&str> = text
+                .lines()
+                .map(|l| l.split_whitespace().nth(1).unwrap())
+                .collect();
+            assert_eq!(
+                kinds,
+                ["Var", "Ident", "Eq", "Number", "Semicolon", "Print", "Ident", "Semicolon", "Eof"]
+            );
+            assert!(text.contains("Ident \"x\""), "{text}");
+            assert!(text.lines().last().unwrap().starts_with("   2"), "{text}");
+        }
+    }
+
+    mod string_escapes {
+        use super::*;
+
+        #[test]
+        fn unicode_escapes_decode() {
+            expect_printed("print \"\\u{41}\";", "A\n");
+            expect_printed("print \"\\u{1F600}\";", "\u{1F600}\n");
+            expect_printed("print len(\"\\u{7F}\");", "1\n");
+        }
+
+        #[test]
+        fn raw_strings_keep_backslashes() {
+            expect_printed(
+                r#"print r"C:\temp\n";"#,
+                "C:\\temp\\n\n",
+            );
+            expect_printed(r#"print len(r"\u{41}");"#, "6\n");
+        }
+
+        #[test]
+        fn invalid_scalars_are_compile_errors() {
+            expect_compile_error(
+                "print \"\\u{110000}\";",
+                "Invalid Unicode scalar in '\\u{' escape.",
+            );
+            expect_compile_error(
+                "print \"\\u{D800}\";",
+                "Invalid Unicode scalar in '\\u{' escape.",
+            );
+            expect_compile_error("print \"\\u{}\";", "Expect 1 to 6 hex digits");
+            expect_compile_error("print \"\\u{41\";", "Unterminated '\\u{' escape.");
+        }
+    }
+
+    mod do_expr {
+        use super::*;
+
+        #[test]
+        fn value_of_trailing_expression() {
+            expect_printed(
+                "var x = do { var a = 2; a + 3 }; print x;",
+                "5\n",
+            );
+        }
+
+        #[test]
+        fn statements_run_and_locals_are_scoped() {
+            expect_printed(
+                r#"
+                var log = "";
+                var x = do {
+                    log = log + "setup;";
+                    var temp = 10;
+                    temp * 2
+                };
+                print log;
+                print x;
+                var y = x + 1;
+                print y;
+                "#,
+                "setup;\n20\n21\n",
+            );
+        }
+
+        #[test]
+        fn block_without_trailing_expression_is_nil() {
+            expect_printed("var x = do { var a = 1; a; }; print x;", "nil\n");
+        }
+
+        #[test]
+        fn nests_inside_calls() {
+            expect_printed(
+                "fun f(n) { return n * 2; } print f(do { 3 + 4 });",
+                "14\n",
+            );
+        }
+    }
+
+    mod incdec {
+        use super::*;
+
+        #[test]
+        fn postfix_in_loop() {
+            expect_printed(
+                "var s = \"\"; for (var i = 0; i < 3; i++) { s = s + \"x\"; } print s;",
+                "xxx\n",
+            );
+        }
+
+        #[test]
+        fn postfix_yields_old_value() {
+            expect_printed("var i = 1; print i++; print i;", "1\n2\n");
+        }
+
+        #[test]
+        fn prefix_yields_new_value() {
+            expect_printed("var x = 5; print --x; print x;", "4\n4\n");
+        }
+
+        #[test]
+        fn fields_increment_in_place() {
+            expect_printed(
+                "class C { init() { this.n = 0; } } var c = C(); c.n++; print c.n; print ++c.n;",
+                "1\n2\n",
+            );
+        }
+
+        #[test]
+        fn non_number_target_errors_at_runtime() {
+            expect_runtime_error(
+                "var s = \"hi\"; s++;",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+
+        #[test]
+        fn non_lvalue_target_errors_at_compile_time() {
+            expect_compile_error("++1;", "Expect variable name after prefix operator.");
+        }
+    }
+
+    mod destructuring {
+        use super::*;
+
+        #[test]
+        fn binds_list_elements_to_globals() {
+            expect_printed(
+                "var xs = [10, 20]; var [a, b] = xs; print a; print b;",
+                "10\n20\n",
+            );
+        }
+
+        #[test]
+        fn binds_list_elements_to_locals() {
+            expect_printed(
+                "{ var [a, b, c] = [1, 2, 3]; print a + b + c; } print \"done\";",
+                "6\ndone\n",
+            );
+        }
+
+        #[test]
+        fn too_few_elements_is_a_range_error() {
+            expect_runtime_error(
+                "var [a, b] = [1];",
+                "List index 1 out of range.",
+            );
+        }
+    }
+
+    mod variadics {
+        use super::*;
+
+        #[test]
+        fn rest_collects_surplus_args() {
+            expect_printed(
+                r#"
+                fun f(first, ...rest) {
+                    print first;
+                    print len(rest);
+                    print rest;
+                }
+                f(1, 2, 3, 4);
+                "#,
+                "1\n3\n[2, 3, 4]\n",
+            );
+        }
+
+        #[test]
+        fn rest_may_be_empty() {
+            expect_printed(
+                "fun f(a, ...rest) { print rest; } f(1);",
+                "[]\n",
+            );
+        }
+
+        #[test]
+        fn required_args_still_checked() {
+            expect_runtime_error(
+                "fun f(a, b, ...rest) { } f(1);",
+                "Function(f) expects at least 2 args, got 1.",
+            );
+        }
+    }
+
+    mod closures {
+        use super::*;
+
+        #[test]
+        fn captures_local() {
+            expect_printed(
+                r#"
+                fun makeCounter() {
+                    var count = 0;
+                    fun counter() {
+                        count = count + 1;
+                        return count;
+                    }
+                    return counter;
+                }
+                var c = makeCounter();
+                print c();
+                print c();
+                "#,
+                "1\n2\n",
+            );
+        }
+
+        #[test]
+        fn closes_over_popped_scope() {
+            expect_printed(
+                r#"
+                var f;
+                {
+                    var text = "captured";
+                    fun inner() { print text; }
+                    f = inner;
+                }
+                f();
+                "#,
+                "captured\n",
+            );
+        }
+
+        #[test]
+        fn shared_upvalue() {
+            expect_printed(
+                r#"
+                var get; var set;
+                {
+                    var x = 1;
+                    fun g() { return x; }
+                    fun s(v) { x = v; }
+                    get = g; set = s;
+                }
+                set(5);
+                print get();
+                "#,
+                "5\n",
+            );
+        }
+    }
+
+    mod class {
+        use super::*;
+
+        #[test]
+        fn fields_and_methods() {
+            expect_printed(
+                r#"
+                class Point {
+                    init(x, y) { this.x = x; this.y = y; }
+                    sum() { return this.x + this.y; }
+                }
+                var p = Point(3, 4);
+                print p.sum();
+                p.x = 10;
+                print p.sum();
+                "#,
+                "7\n14\n",
+            );
+        }
+
+        #[test]
+        fn bound_method_keeps_this() {
+            expect_printed(
+                r#"
+                class Greeter {
+                    init(name) { this.name = name; }
+                    greet() { print this.name; }
+                }
+                var m = Greeter("bound").greet;
+                m();
+                "#,
+                "bound\n",
+            );
+        }
+
+        #[test]
+        fn this_outside_class() {
+            expect_compile_error("print this;", "Cannot use 'this' outside of a class.");
+        }
+
+        #[test]
+        fn direct_invocation_skips_binding() {
+            let mut vm = crate::VM::new();
+            let listing = vm
+                .dump("class A { m() {} } A().m(1, 2);")
+                .unwrap();
+            assert!(listing.contains("Invoke"), "listing:\n{listing}");
+        }
+
+        #[test]
+        fn function_in_field_is_called() {
+            expect_printed(
+                r#"
+                fun twice(x) { return x * 2; }
+                class Holder {}
+                var h = Holder();
+                h.f = twice;
+                print h.f(21);
+                "#,
+                "42\n",
+            );
+        }
+
+        #[test]
+        fn call_nonfunction_field() {
+            expect_runtime_error(
+                "class A {} var a = A(); a.x = 3; a.x();",
+                "Can only call functions and classes.",
+            );
+        }
+    }
+
+    mod field {
+        use super::*;
+
+        #[test]
+        fn read_of_non_instance() {
+            expect_runtime_error("var x = 1; print x.field;", "Cannot read property of non-instance.");
+        }
+
+        #[test]
+        fn write_to_non_instance() {
+            expect_runtime_error("var x = 1; x.field = 2;", "Cannot write property of non-instance.");
+        }
+
+        #[test]
+        fn undefined_property() {
+            expect_runtime_error(
+                "class A {} var a = A(); print a.missing;",
+                "Undefined property 'missing'.",
+            );
+        }
+
+        #[test]
+        fn frozen_instance_rejects_writes() {
+            expect_printed(
+                r#"
+                class P { init() { this.x = 1; } get() { return this.x; } }
+                var p = freeze(P());
+                print p.x;
+                print p.get();
+                try { p.x = 2; } catch (e) { print e; }
+                try { set(p, "x", 2); } catch (e) { print e; }
+                print p.x;
+                "#,
+                "1\n1\nCannot write property of frozen instance.\nCannot write property of frozen instance.\n1\n",
+            );
+        }
+
+        #[test]
+        fn freeze_requires_an_instance() {
+            expect_runtime_error("freeze(1);", "freeze() expects an instance argument.");
+        }
+
+        #[test]
+        fn cached_read_sees_fields_added_later() {
+            // the first `p.x` read caches its fields-table slot; adding
+            // enough fields afterwards forces a rehash, which must fall back
+            // to a probe rather than read a stale slot
+            expect_printed(
+                r#"
+                class P { init() { this.x = 1; } }
+                var p = P();
+                print p.x;
+                p.a = 0; p.b = 0; p.c = 0; p.d = 0; p.e = 0; p.f = 0; p.g = 0;
+                print p.x;
+                p.x = 2;
+                print p.x;
+                "#,
+                "1\n1\n2\n",
+            );
+        }
+
+        #[test]
+        fn cached_read_handles_divergent_layouts() {
+            // one call site, two instances whose field tables place `x` in
+            // different slots: the identity check must reject the cached
+            // slot for the second instance
+            expect_printed(
+                r#"
+                class P { init(extra) { if (extra) { this.pad = 0; } this.x = extra; } }
+                fun read(p) { return p.x; }
+                var a = P(false);
+                var b = P(true);
+                print read(a);
+                print read(b);
+                print read(a);
+                "#,
+                "false\ntrue\nfalse\n",
+            );
+        }
+    }
+
+    mod try_catch {
+        use super::*;
+
+        use crate::InterpretError;
+
+        #[test]
+        fn catches_runtime_error() {
+            expect_printed(
+                r#"
+                try {
+                    var x = 1 + "one";
+                    print "unreachable";
+                } catch (e) {
+                    print e;
+                }
+                print "after";
+                "#,
+                "Operands must be two numbers or two strings.\nafter\n",
+            );
+        }
+
+        #[test]
+        fn unwinds_through_calls() {
+            expect_printed(
+                r#"
+                fun explode() { return missing_global; }
+                fun middle() { return explode(); }
+                try {
+                    middle();
+                } catch (e) {
+                    print e;
+                }
+                "#,
+                "Undefined variable 'missing_global'.\n",
+            );
+        }
+
+        #[test]
+        fn nested_handlers() {
+            expect_printed(
+                r#"
+                try {
+                    try {
+                        1 / "x";
+                    } catch (inner) {
+                        print "inner: " + inner;
+                        missing;
+                    }
+                } catch (outer) {
+                    print "outer: " + outer;
+                }
+                "#,
+                "inner: Operands must be numbers.\nouter: Undefined variable 'missing'.\n",
+            );
+        }
+
+        #[test]
+        fn uncaught_error_still_fails() {
+            expect_runtime_error(
+                "try { 1 + 1; } catch (e) { print e; } missing;",
+                "Undefined variable 'missing'.",
+            );
+        }
+
+        #[test]
+        fn return_inside_try_discards_handler() {
+            // the error after the call must not land in the returned-from
+            // function's catch block
+            let (result, printed) = run(
+                r#"
+                fun f() {
+                    try {
+                        return "from try";
+                    } catch (e) {
+                        print "not here";
+                    }
+                }
+                print f();
+                missing;
+                "#,
+            );
+            assert_eq!(printed, "from try\n");
+            assert!(matches!(
+                result,
+                Err(InterpretError::RuntimeError { msg, .. }) if msg.contains("Undefined variable 'missing'.")
+            ));
+        }
+    }
+
+    mod string_compare {
+        use super::*;
+
+        #[test]
+        fn lexicographic_ordering() {
+            expect_printed(
+                "print \"apple\" < \"banana\"; print \"b\" < \"a\";",
+                "true\nfalse\n",
+            );
+        }
+
+        #[test]
+        fn derived_comparisons() {
+            expect_printed(
+                "print \"a\" <= \"a\"; print \"b\" > \"a\"; print \"a\" >= \"b\";",
+                "true\ntrue\nfalse\n",
+            );
+        }
+
+        #[test]
+        fn mixed_operands_still_error() {
+            expect_runtime_error(
+                "print \"a\" < 1;",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+    }
+
+    mod bitwise {
+        use super::*;
+
+        #[test]
+        fn and_or_xor() {
+            expect_printed("print 6 & 3; print 6 | 3; print 6 ^ 3;", "2\n7\n5\n");
+        }
+
+        #[test]
+        fn shifts() {
+            expect_printed("print 1 << 4; print 256 >> 4;", "16\n16\n");
+        }
+
+        #[test]
+        fn bit_not() {
+            expect_printed("print ~0; print ~5;", "-1\n-6\n");
+        }
+
+        #[test]
+        fn precedence_binds_tighter_than_equality() {
+            expect_printed("print (6 & 3) == 2; print 1 << 2 + 1;", "true\n8\n");
+        }
+
+        #[test]
+        fn non_whole_operands_error() {
+            expect_runtime_error("print 1.5 & 2;", "Operands must be whole numbers.");
+        }
+
+        #[test]
+        fn shift_count_out_of_range() {
+            expect_runtime_error("print 1 << 64;", "Shift count out of range.");
+            expect_runtime_error("print 1 << -1;", "Shift count out of range.");
+        }
+    }
+
+    mod string_coercion {
+        use crate::test_utils::Capture;
+        use crate::VM;
+
+        fn run_coercing(source: &str) -> String {
+            let mut vm = VM::new().with_string_coercion(true);
+            let capture = Capture::default();
+            vm.set_output(Box::new(capture.clone()));
+            vm.interpret(source).unwrap();
+            capture.contents()
+        }
+
+        #[test]
+        fn string_plus_number() {
+            assert_eq!(run_coercing("print \"x=\" + 5;"), "x=5\n");
+        }
+
+        #[test]
+        fn number_plus_string() {
+            assert_eq!(run_coercing("print 5 + \"x\";"), "5x\n");
+        }
+
+        #[test]
+        fn strict_by_default() {
+            super::expect_runtime_error(
+                "print \"x=\" + 5;",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+    }
+
+    mod total_ordering {
+        use crate::test_utils::Capture;
+        use crate::VM;
+
+        fn run_ordered(source: &str) -> String {
+            let mut vm = VM::new().with_total_ordering(true);
+            let capture = Capture::default();
+            vm.set_output(Box::new(capture.clone()));
+            vm.interpret(source).unwrap();
+            capture.contents()
+        }
+
+        #[test]
+        fn ranks_across_types() {
+            assert_eq!(run_ordered("print nil < false;"), "true\n");
+            assert_eq!(run_ordered("print false < 1;"), "true\n");
+            assert_eq!(run_ordered("print 1 < \"a\";"), "true\n");
+            assert_eq!(run_ordered("print false < true;"), "true\n");
+            assert_eq!(run_ordered("print \"a\" > 100;"), "true\n");
+        }
+
+        #[test]
+        fn unordered_types_still_error() {
+            let mut vm = VM::new().with_total_ordering(true);
+            vm.set_output(Box::new(std::io::sink()));
+            let err = vm.interpret("class A {} print A() < 1;").unwrap_err();
+            assert!(err.to_string().contains("Operands must be orderable values."));
+        }
+
+        #[test]
+        fn strict_by_default() {
+            super::expect_runtime_error(
+                "print nil < false;",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+    }
+
+    mod reflection {
+        use super::*;
+
+        #[test]
+        fn fields_lists_field_names() {
+            expect_printed(
+                r#"
+                class Point {}
+                var p = Point();
+                p.x = 1; p.y = 2;
+                var names = fields(p);
+                print len(names);
+                print contains(format("{}", names), "x");
+                print contains(format("{}", names), "y");
+                "#,
+                "2\ntrue\ntrue\n",
+            );
+        }
+
+        #[test]
+        fn dynamic_field_round_trip() {
+            expect_printed(
+                r#"
+                class Bag {}
+                var b = Bag();
+                print has(b, "answer");
+                set(b, "answer", 42);
+                print has(b, "answer");
+                print get(b, "answer");
+                print b.answer;
+                print get(b, "missing");
+                "#,
+                "false\ntrue\n42\n42\nnil\n",
+            );
+        }
+
+        #[test]
+        fn reflection_natives_reject_bad_arguments() {
+            expect_runtime_error(
+                "get(1, \"x\");",
+                "get() expects an instance and a string field name.",
+            );
+            expect_runtime_error(
+                "class C {} set(C(), 1, 2);",
+                "set() expects an instance and a string field name.",
+            );
+        }
+
+        #[test]
+        fn fields_rejects_non_instances() {
+            expect_runtime_error("fields(42);", "fields() expects an instance argument.");
+        }
+    }
+
+    mod display_cycles {
+        use super::*;
+
+        #[test]
+        fn self_referential_instance_prints_cycle() {
+            expect_printed(
+                "class Foo {} var f = Foo(); f.me = f; print f;",
+                "Foo{ me: <cycle> }\n",
+            );
+        }
+
+        #[test]
+        fn two_node_cycle_prints_without_overflow() {
+            expect_printed(
+                r#"
+                class Node {}
+                var a = Node(); var b = Node();
+                a.next = b; b.next = a;
+                print a;
+                "#,
+                "Node{ next: Node{ next: <cycle> } }\n",
+            );
+        }
+
+        #[test]
+        fn self_referential_list_prints_cycle() {
+            expect_printed("var l = [1]; l[0] = l; print l;", "[<cycle>]\n");
+        }
+    }
+
+    mod gc {
+        use crate::VM;
+
+        #[test]
+        fn duplicate_concatenation_is_not_recounted() {
+            let mut vm = VM::new();
+            vm.interpret("var a = \"foo\"; var b = \"bar\"; var first = a + b;")
+                .unwrap();
+            let before = vm.gc_stats().bytes_allocated;
+            // same concatenation again: the result interns to the existing
+            // string, so the allocation stats must not grow
+            vm.interpret("first = a + b;").unwrap();
+            assert_eq!(vm.gc_stats().bytes_allocated, before);
+        }
+
+        #[test]
+        fn finalizer_runs_exactly_once_on_collection() {
+            let mut vm = VM::new();
+            let out = crate::test_utils::Capture::default();
+            vm.set_output(Box::new(out.clone()));
+            vm.interpret(
+                r#"
+                class Resource {
+                    finalize() { print "closed"; }
+                }
+                { var r = Resource(); }
+                "#,
+            )
+            .unwrap();
+            vm.collect_garbage();
+            // the instance is already gone; a second collection must not
+            // re-run its finalizer
+            vm.collect_garbage();
+            assert_eq!(out.contents(), "closed\n");
+        }
+
+        #[test]
+        fn resurrected_instance_is_not_finalized_again() {
+            let mut vm = VM::new();
+            let out = crate::test_utils::Capture::default();
+            vm.set_output(Box::new(out.clone()));
+            vm.interpret(
+                r#"
+                var keeper = nil;
+                class Phoenix {
+                    finalize() { print "dying"; keeper = this; }
+                }
+                { var p = Phoenix(); }
+                "#,
+            )
+            .unwrap();
+            vm.collect_garbage();
+            vm.interpret("print keeper != nil; keeper = nil;").unwrap();
+            vm.collect_garbage();
+            assert_eq!(out.contents(), "dying\ntrue\n");
+        }
+
+        #[test]
+        fn weakref_target_dies_with_its_instance() {
+            let mut vm = VM::new();
+            let out = crate::test_utils::Capture::default();
+            vm.set_output(Box::new(out.clone()));
+            vm.interpret(
+                r#"
+                class C {}
+                var w = nil;
+                { var c = C(); c.x = 1; w = weakref(c); print deref(w).x; }
+                "#,
+            )
+            .unwrap();
+            // the instance's only remaining owner is the heap-object list
+            vm.collect_garbage();
+            vm.interpret("print deref(w); print typeof(w);").unwrap();
+            assert_eq!(out.contents(), "1\nnil\nweakref\n");
+        }
+
+        #[test]
+        fn tiny_threshold_collects_during_allocation() {
+            let mut vm = VM::new().with_gc(64, 2);
+            let baseline = vm.gc_stats().bytes_allocated;
+            // each iteration replaces `s`, orphaning the previous string;
+            // without collections this would hold ~N^2/2 bytes
+            vm.interpret(
+                r#"
+                var s = "";
+                for (var i = 0; i < 100; i = i + 1) { s = s + "x"; }
+                "#,
+            )
+            .unwrap();
+            let stats = vm.gc_stats();
+            let grown = stats.bytes_allocated - baseline;
+            assert!(grown < 2000, "GC never ran: {stats:?}");
+            assert!(stats.next_gc >= 64);
+        }
+
+        #[test]
+        fn sweep_frees_unreachable_closures() {
+            let mut vm = VM::new();
+            vm.interpret(
+                r#"
+                fun outer() {
+                    var captured = "payload";
+                    fun inner() { return captured; }
+                    return inner;
+                }
+                for (var i = 0; i < 100; i = i + 1) {
+                    outer()();
+                }
+                "#,
+            )
+            .unwrap();
+            let before = vm.gc_stats().bytes_allocated;
+            vm.collect_garbage();
+            assert!(
+                vm.gc_stats().bytes_allocated < before,
+                "expected sweep to free dead closures ({before} bytes before)"
+            );
+        }
+    }
+
+    mod throw {
+        use super::*;
+
+        use crate::InterpretError;
+
+        #[test]
+        fn throw_and_catch_string() {
+            expect_printed(
+                r#"
+                try {
+                    throw "boom";
+                } catch (e) {
+                    print "caught: " + e;
+                }
+                "#,
+                "caught: boom\n",
+            );
+        }
+
+        #[test]
+        fn thrown_value_keeps_its_type() {
+            expect_printed(
+                r#"
+                class Error {
+                    init(code) { this.code = code; }
+                }
+                try {
+                    throw Error(42);
+                } catch (e) {
+                    print typeof(e);
+                    print e.code;
+                }
+                "#,
+                "instance\n42\n",
+            );
+        }
+
+        #[test]
+        fn throw_unwinds_through_calls() {
+            expect_printed(
+                r#"
+                fun inner() { throw "deep"; }
+                fun outer() { inner(); }
+                try { outer(); } catch (e) { print e; }
+                "#,
+                "deep\n",
+            );
+        }
+
+        #[test]
+        fn uncaught_throw_is_a_runtime_error() {
+            let (result, _) = run("throw \"boom\";");
+            assert!(matches!(
+                result,
+                Err(InterpretError::RuntimeError { msg, .. }) if msg == "Uncaught error: boom"
+            ));
+        }
+    }
+
+    mod statics {
+        use super::*;
+
+        #[test]
+        fn static_method_on_class() {
+            expect_printed(
+                r#"
+                class Math {
+                    static square(x) { return x * x; }
+                }
+                print Math.square(3);
+                "#,
+                "9\n",
+            );
+        }
+
+        #[test]
+        fn this_in_static_is_rejected() {
+            expect_compile_error(
+                "class A { static m() { return this; } }",
+                "Cannot use 'this' in a static method.",
+            );
+        }
+
+        #[test]
+        fn missing_static_is_undefined() {
+            expect_runtime_error(
+                "class A {} A.nope;",
+                "Undefined property 'nope'.",
+            );
+        }
+    }
+
+    mod getters {
+        use super::*;
+
+        #[test]
+        fn getter_computes_value() {
+            expect_printed(
+                r#"
+                class Square {
+                    init(side) { this.side = side; }
+                    area { return this.side * this.side; }
+                }
+                print Square(4).area;
+                "#,
+                "16\n",
+            );
+        }
+
+        #[test]
+        fn field_shadows_getter() {
+            expect_printed(
+                r#"
+                class A {
+                    value { return "getter"; }
+                }
+                var a = A();
+                print a.value;
+                a.value = "field";
+                print a.value;
+                "#,
+                "getter\nfield\n",
+            );
+        }
+
+        #[test]
+        fn getter_init_is_rejected() {
+            expect_compile_error(
+                "class A { init { } }",
+                "An initializer cannot be a getter.",
+            );
+        }
+    }
+
+    mod operators {
+        use super::*;
+
+        #[test]
+        fn overloaded_add() {
+            expect_printed(
+                r#"
+                class Vector {
+                    init(x, y) { this.x = x; this.y = y; }
+                    add(other) { return Vector(this.x + other.x, this.y + other.y); }
+                }
+                var v = Vector(1, 2) + Vector(3, 4);
+                print v.x;
+                print v.y;
+                "#,
+                "4\n6\n",
+            );
+        }
+
+        #[test]
+        fn overloaded_comparison_and_eq() {
+            expect_printed(
+                r#"
+                class Money {
+                    init(cents) { this.cents = cents; }
+                    lt(other) { return this.cents < other.cents; }
+                    eq(other) { return this.cents == other.cents; }
+                }
+                print Money(5) < Money(9);
+                print Money(5) == Money(5);
+                print Money(5) == Money(9);
+                "#,
+                "true\ntrue\nfalse\n",
+            );
+        }
+
+        #[test]
+        fn missing_overload_keeps_type_error() {
+            expect_runtime_error(
+                "class A {} A() + 1;",
+                "Operands must be two numbers or two strings.",
+            );
+        }
+    }
+
+    mod inheritance {
+        use super::*;
+
+        #[test]
+        fn inherits_methods() {
+            expect_printed(
+                r#"
+                class Base { speak() { print "base"; } }
+                class Derived < Base {}
+                Derived().speak();
+                "#,
+                "base\n",
+            );
+        }
+
+        #[test]
+        fn super_call() {
+            expect_printed(
+                r#"
+                class Base { speak() { print "base"; } }
+                class Derived < Base {
+                    speak() { super.speak(); print "derived"; }
+                }
+                Derived().speak();
+                "#,
+                "base\nderived\n",
+            );
+        }
+
+        #[test]
+        fn super_method_as_value() {
+            expect_printed(
+                r#"
+                class Base { speak() { print "base"; } }
+                class Derived < Base {
+                    grab() { return super.speak; }
+                }
+                var m = Derived().grab();
+                m();
+                "#,
+                "base\n",
+            );
+        }
+
+        #[test]
+        fn super_member_must_be_a_method() {
+            expect_runtime_error(
+                r#"
+                class Base {}
+                class Derived < Base {
+                    poke() { return super.missing; }
+                }
+                Derived().poke();
+                "#,
+                "Undefined property 'missing'.",
+            );
+        }
+
+        #[test]
+        fn is_walks_the_superclass_chain() {
+            expect_printed(
+                r#"
+                class Base {}
+                class Mid < Base {}
+                class Derived < Mid {}
+                var d = Derived();
+                print is(d, Derived);
+                print is(d, Mid);
+                print is(d, Base);
+                print is(Base(), Derived);
+                "#,
+                "true\ntrue\ntrue\nfalse\n",
+            );
+        }
+
+        #[test]
+        fn methods_resolve_through_the_chain() {
+            expect_printed(
+                r#"
+                class Base { speak() { print "base"; } init() { this.x = 1; } }
+                class Mid < Base {}
+                class Derived < Mid { other() { this.speak(); } }
+                var d = Derived();
+                d.speak();
+                d.other();
+                print d.x;
+                "#,
+                "base\nbase\n1\n",
+            );
+        }
+
+        #[test]
+        fn superclass_reflection() {
+            expect_printed(
+                r#"
+                class Base {}
+                class Derived < Base {}
+                print superclass(Derived);
+                print superclass(Derived());
+                print superclass(Base);
+                "#,
+                "Base\nBase\nnil\n",
+            );
+        }
+
+        #[test]
+        fn is_rejects_bad_arguments() {
+            expect_runtime_error("is(1, 2);", "is() expects an instance and a class.");
+        }
+
+        #[test]
+        fn inherit_from_non_class() {
+            expect_runtime_error(
+                "var NotAClass = 1; class A < NotAClass {}",
+                "Superclass must be a class.",
+            );
+        }
+
+        #[test]
+        fn inherit_from_self() {
+            expect_compile_error("class A < A {}", "A class cannot inherit from itself.");
+        }
+    }
+
+    mod list {
+        use super::*;
+
+        #[test]
+        fn literal_prints() {
+            expect_printed("print [1, 2, 3];", "[1, 2, 3]\n");
+            expect_printed("print [];", "[]\n");
+            expect_printed("print [nil, true, \"s\", [1]];", "[nil, true, s, [1]]\n");
+        }
+
+        #[test]
+        fn index_read_and_write() {
+            expect_printed(
+                r#"
+                var xs = [1, 2, 3];
+                print xs[0];
+                xs[1] = 20;
+                print xs[1];
+                print xs;
+                "#,
+                "1\n20\n[1, 20, 3]\n",
+            );
+        }
+
+        #[test]
+        fn index_out_of_range() {
+            expect_runtime_error("var xs = [1]; xs[1];", "List index 1 out of range.");
+            expect_runtime_error("var xs = [1]; xs[0 - 1];", "out of range.");
+            expect_runtime_error("var xs = [1]; xs[0.5];", "out of range.");
+        }
+
+        #[test]
+        fn index_non_list() {
+            expect_runtime_error("var x = 1; x[0];", "Can only index into lists.");
+            expect_runtime_error("var xs = [1]; xs[\"a\"];", "List index must be a number.");
+        }
+
+        #[test]
+        fn foreach_sums_elements() {
+            expect_printed(
+                r#"
+                var total = 0;
+                for (x in [1, 2, 3, 4]) {
+                    total = total + x;
+                }
+                print total;
+                "#,
+                "10\n",
+            );
+        }
+
+        #[test]
+        fn foreach_loop_variable_is_fresh() {
+            // each closure captures its own `x`, not the final value
+            expect_printed(
+                r#"
+                var fns = [nil, nil, nil];
+                var i = 0;
+                for (x in [1, 2, 3]) {
+                    fun get() { return x; }
+                    fns[i] = get;
+                    i = i + 1;
+                }
+                print fns[0]() + fns[1]() + fns[2]();
+                "#,
+                "6\n",
+            );
+        }
+
+        #[test]
+        fn foreach_over_non_list() {
+            expect_runtime_error("for (x in 1) {}", "Can only take the length of a list.");
+        }
+    }
+
+    mod natives {
+        use super::*;
+
+        #[test]
+        fn math_conveniences() {
+            expect_printed(
+                r#"
+                print clamp(5, 0, 3);
+                print clamp(-1, 0, 3);
+                print clamp(2, 0, 3);
+                print lerp(0, 10, 0.5);
+                print lerp(0, 10, 1.5);
+                print sign(-2);
+                print sign(7);
+                print sign(0);
+                "#,
+                "3\n0\n2\n5\n15\n-1\n1\n0\n",
+            );
+        }
+
+        #[test]
+        fn clamp_rejects_inverted_bounds() {
+            expect_runtime_error("clamp(1, 3, 0);", "clamp() expects lo <= hi");
+        }
+
+        #[test]
+        fn math_conveniences_require_numbers() {
+            expect_runtime_error("clamp(\"x\", 0, 1);", "clamp() expects number arguments");
+            expect_runtime_error("lerp(0, 1);", "lerp() expects number arguments");
+            expect_runtime_error("sign(nil);", "sign() expects a number argument.");
+        }
+
+        #[test]
+        fn chars_splits_on_scalar_boundaries() {
+            expect_printed(
+                r#"
+                var cs = chars("a\u{e9}");
+                print len(cs);
+                print cs[1];
+                print len(chars(""));
+                "#,
+                "2\n\u{e9}\n0\n",
+            );
+            expect_runtime_error("chars(1);", "chars() expects a string argument.");
+        }
+
+        #[test]
+        fn contains_and_index_of() {
+            expect_printed(
+                r#"
+                print contains("hello world", "lo w");
+                print contains("hello", "xyz");
+                print index_of("hello", "llo");
+                print index_of("hello", "xyz");
+                print index_of("hello", "");
+                "#,
+                "true\nfalse\n2\n-1\n0\n",
+            );
+        }
+
+        #[test]
+        fn substr_slices_by_character() {
+            expect_printed(
+                r#"
+                print substr("hello world", 6, 5);
+                print substr("short", 2, 100);
+                print substr("short", 99, 3);
+                "#,
+                "world\nort\n\n",
+            );
+        }
+
+        #[test]
+        fn substr_handles_multibyte() {
+            expect_printed("print substr(\"日本語abc\", 1, 3);", "本語a\n");
+        }
+
+        #[test]
+        fn substr_rejects_bad_indices() {
+            expect_runtime_error(
+                "substr(\"abc\", -1, 2);",
+                "substr() start and length must be whole non-negative numbers.",
+            );
+        }
+
+        #[test]
+        fn ord_chr_round_trip() {
+            expect_printed(
+                "print ord(\"A\"); print chr(65); print chr(ord(\"Z\"));",
+                "65\nA\nZ\n",
+            );
+        }
+
+        #[test]
+        fn ord_chr_errors() {
+            expect_runtime_error("ord(\"\");", "ord() expects a single-character string.");
+            expect_runtime_error("ord(\"ab\");", "ord() expects a single-character string.");
+            expect_runtime_error("chr(55296);", "is not a Unicode scalar value.");
+        }
+
+        #[test]
+        fn split_string_into_list() {
+            expect_printed(
+                r#"
+                var parts = split("a,b,c", ",");
+                print len(parts);
+                print parts[0]; print parts[1]; print parts[2];
+                print split("abc", "");
+                print split("xyz", ",");
+                "#,
+                "3\na\nb\nc\n[a, b, c]\n[xyz]\n",
+            );
+        }
+
+        #[test]
+        fn arity_of_callables() {
+            expect_printed(
+                "fun f(a, b, c) {} print arity(f); print arity(clock);",
+                "3\nnil\n",
+            );
+        }
+
+        #[test]
+        fn min_max_variadic() {
+            expect_printed(
+                "print max(1, 5, 3); print min(4, 2); print min(9);",
+                "5\n2\n9\n",
+            );
+        }
+
+        #[test]
+        fn min_max_errors() {
+            expect_runtime_error("min();", "min() expects at least one argument.");
+            expect_runtime_error("max(1, \"two\");", "max() expects number arguments.");
+        }
+
+        #[test]
+        fn rng_is_deterministic_per_seed() {
+            expect_printed(
+                r#"
+                seed_rng(42);
+                var a = random(); var b = random(); var c = random();
+                seed_rng(42);
+                print random() == a; print random() == b; print random() == c;
+                print a >= 0 and a < 1;
+                seed_rng(7);
+                print random() == a;
+                "#,
+                "true\ntrue\ntrue\ntrue\nfalse\n",
+            );
+        }
+
+        #[test]
+        fn clock_is_non_decreasing() {
+            expect_printed(
+                "var a = clock(); var b = clock(); print b >= a; print a >= 0;",
+                "true\ntrue\n",
+            );
+        }
+
+        #[test]
+        fn type_of_each_kind() {
+            expect_printed(
+                r#"
+                print typeof(nil);
+                print typeof(true);
+                print typeof(1.5);
+                print typeof("s");
+                fun f() {}
+                print typeof(f);
+                print typeof(clock);
+                class A { m() {} }
+                print typeof(A);
+                print typeof(A());
+                print typeof(A().m);
+                print typeof([]);
+                "#,
+                "nil\nbool\nnumber\nstring\nfunction\nfunction\nclass\ninstance\nfunction\nlist\n",
+            );
+        }
+
+        #[test]
+        fn assert_passes_when_truthy() {
+            expect_printed("assert(1 == 1); assert(true, \"unused\"); print \"ok\";", "ok\n");
+        }
+
+        #[test]
+        fn assert_failure_raises() {
+            expect_runtime_error("assert(false, \"boom\");", "boom");
+            expect_runtime_error("assert(1 == 2);", "Assertion failed.");
+        }
+
+        #[test]
+        fn format_substitutes_placeholders() {
+            expect_printed(
+                "print format(\"{} + {} = {}\", 1, 2, 3);",
+                "1 + 2 = 3\n",
+            );
+            expect_printed("print format(\"no holes\");", "no holes\n");
+        }
+
+        #[test]
+        fn format_argument_mismatch() {
+            expect_runtime_error(
+                "format(\"{} {}\", 1);",
+                "Not enough arguments for format string.",
+            );
+            expect_runtime_error(
+                "format(\"{}\", 1, 2);",
+                "Too many arguments for format string.",
+            );
+        }
+
+        #[test]
+        fn num_parses_strings() {
+            expect_printed("print num(\"42\");", "42\n");
+            expect_printed("print num(\"2.5\");", "2.5\n");
+            expect_printed("print num(\"  -3 \");", "-3\n");
+        }
+
+        #[test]
+        fn num_rejects_garbage() {
+            expect_runtime_error("num(\"abc\");", "Cannot convert 'abc' to a number.");
+            expect_runtime_error("num(\"\");", "Cannot convert empty string to a number.");
+        }
+
+        #[test]
+        fn len_of_lists_and_strings() {
+            expect_printed("print len([1, 2, 3]);", "3\n");
+            expect_printed("print len([]);", "0\n");
+            expect_printed("print len(\"abc\");", "3\n");
+            expect_runtime_error("len(1);", "len() expects a list or string argument.");
+        }
+
+        #[test]
+        fn type_of_compares_equal() {
+            expect_printed("print typeof(1) == \"number\";", "true\n");
+        }
+
+        #[test]
+        fn debug_exposes_debug_formatting() {
+            expect_printed("print debug(123);", "Float(123.0)\n");
+            expect_printed("print debug(\"a\");", "String(\"a\")\n");
+            expect_printed("print debug(nil);", "Nil\n");
+        }
+    }
+
+    mod constants {
+        use crate::value::Value;
+        use crate::VM;
+
+        #[test]
+        fn shared_name_occupies_one_slot() {
+            let mut vm = VM::new();
+            let mut source = String::from("class C {\n");
+            for i in 0..8 {
+                source.push_str(&format!("    m{i}() {{ return helper; }}\n"));
+            }
+            source.push('}');
+            let script = vm.compile(&source).unwrap();
+            // every chunk shares the pool, so "helper" takes one slot total
+            // rather than one per method
+            let occurrences = script
+                .chunk
+                .constants
+                .iter()
+                .filter(|c| matches!(c, Value::String(s) if s.as_ref() == "helper"))
+                .count();
+            assert_eq!(occurrences, 1);
+            // each method's pool is a prefix of the script's: same slots,
+            // minus constants added after the method finished
+            for constant in &script.chunk.constants {
+                if let Value::Function(f) = constant {
+                    let len = f.chunk.constants.len();
+                    assert_eq!(f.chunk.constants[..], script.chunk.constants[..len]);
+                }
+            }
+        }
+
+        #[test]
+        fn zero_and_one_are_immediate() {
+            let mut vm = VM::new();
+            let listing = vm
+                .dump("for (var i = 0; i < 5; i = i + 1) { print i; }")
+                .unwrap();
+            assert!(listing.contains("Zero"), "listing:\n{listing}");
+            assert!(listing.contains("One"), "listing:\n{listing}");
+            // neither literal should have claimed a constant slot
+            let script = vm
+                .compile("for (var i = 0; i < 5; i = i + 1) { print i; }")
+                .unwrap();
+            assert!(!script
+                .chunk
+                .constants
+                .iter()
+                .any(|c| matches!(c, Value::Float(f) if *f == 0.0 || *f == 1.0)));
+        }
+
+        #[test]
+        fn immediate_literals_push_the_right_values() {
+            crate::test_utils::expect_printed(
+                "print 0; print 1; print 0 + 1; print 1 - 0.0;",
+                "0\n1\n1\n1\n",
+            );
+        }
+
+        #[test]
+        fn negative_literal_folds_to_one_constant() {
+            let mut vm = VM::new();
+            let listing = vm.dump("print -5;").unwrap();
+            assert!(listing.contains("Constant"), "listing:\n{listing}");
+            assert!(!listing.contains("Negate"), "listing:\n{listing}");
+            let script = vm.compile("print -5;").unwrap();
+            assert!(script
+                .chunk
+                .constants
+                .iter()
+                .any(|c| matches!(c, Value::Float(f) if *f == -5.0)));
+        }
+
+        #[test]
+        fn folded_negation_preserves_semantics() {
+            crate::test_utils::expect_printed(
+                "print -5; print - -5; print -0.5 * 2; var x = 3; print -x;",
+                "-5\n5\n-1\n-3\n",
+            );
+        }
+
+        #[test]
+        fn repeated_literal_occupies_one_slot() {
+            let mut vm = VM::new();
+            let mut source = String::new();
+            for _ in 0..300 {
+                source.push_str("print 1.5;\n");
+            }
+            // 300 uses of the same literal must not exhaust the 256-slot pool
+            let script = vm.compile(&source).unwrap();
+            let occurrences = script
+                .chunk
+                .constants
+                .iter()
+                .filter(|c| matches!(c, Value::Float(f) if *f == 1.5))
+                .count();
+            assert_eq!(occurrences, 1);
+        }
+    }
+
+    mod dead_code {
+        use super::*;
+
+        use crate::value::Value;
+        use crate::VM;
+
+        /// Compiles `source` and returns the bytecode length of the first
+        /// function constant in the script chunk.
+        fn function_chunk_len(source: &str) -> usize {
+            let mut vm = VM::new();
+            let script = vm.compile(source).unwrap();
+            script
+                .chunk
+                .constants
+                .iter()
+                .find_map(|c| match c {
+                    Value::Function(f) => Some(f.chunk.data.len()),
+                    _ => None,
+                })
+                .unwrap()
+        }
+
+        #[test]
+        fn code_after_return_is_not_emitted() {
+            let clean = function_chunk_len("fun f() { return 1; }");
+            let dead = function_chunk_len("fun f() { return 1; print 2; var x = 3; }");
+            assert_eq!(clean, dead);
+        }
+
+        #[test]
+        fn dead_code_still_parses() {
+            expect_compile_error("fun f() { return 1; print ; }", "Expect expression.");
+        }
+
+        #[test]
+        fn conditional_return_is_not_suppressed() {
+            expect_printed(
+                r#"
+                fun f(c) {
+                    if (c) { return 1; }
+                    return 2;
+                }
+                print f(false);
+                print f(true);
+                "#,
+                "2\n1\n",
+            );
+        }
+
+        #[test]
+        fn dead_code_in_loops_is_not_suppressed_outside() {
+            expect_printed(
+                r#"
+                fun f() {
+                    while (true) { return 1; }
+                    return 2;
+                }
+                print f();
+                "#,
+                "1\n",
+            );
+        }
+    }
+
+    mod warnings {
+        use super::*;
+
+        #[test]
+        fn unused_local_warns() {
+            let logs = capture_logs(|| {
+                let _ = run("fun f() {\n    var unused = 1;\n}\nf();");
+            });
+            assert!(
+                logs.contains("[line 2] Unused local variable 'unused'."),
+                "missing warning in logs: {logs:?}"
+            );
+        }
+
+        #[test]
+        fn unused_block_local_warns() {
+            let logs = capture_logs(|| {
+                let _ = run("{ var x = 1; }");
+            });
+            assert!(logs.contains("Unused local variable 'x'."), "logs: {logs:?}");
+        }
+
+        #[test]
+        fn write_only_local_warns() {
+            let logs = capture_logs(|| {
+                let _ = run("{ var x = 1; x = 2; }");
+            });
+            assert!(logs.contains("Unused local variable 'x'."), "logs: {logs:?}");
+        }
+
+        #[test]
+        fn used_and_underscore_locals_are_silent() {
+            let logs = capture_logs(|| {
+                let _ = run("{ var x = 1; print x; var _scratch = 2; }");
+            });
+            assert!(!logs.contains("Unused local"), "logs: {logs:?}");
+        }
+
+        #[test]
+        fn parameters_are_exempt() {
+            let logs = capture_logs(|| {
+                let _ = run("fun f(a, b) { return a; }\nf(1, 2);");
+            });
+            assert!(!logs.contains("Unused local"), "logs: {logs:?}");
+        }
+    }
+
+    mod error_rendering {
+        use super::*;
+
+        #[test]
+        fn runtime_error_quotes_the_source_line() {
+            let logs = capture_logs(|| {
+                let (result, _) = run("var a = 1;\nprint missing;");
+                assert!(result.is_err());
+            });
+            assert!(logs.contains("Undefined variable 'missing'."), "{logs}");
+            assert!(logs.contains("2 | print missing;"), "{logs}");
+        }
+    }
+
+    mod no_std_build {
+        /// CI-style guard: the library must keep building as `no_std + alloc`
+        /// with the `std` feature disabled. Artifacts are cached, so this is
+        /// only slow the first time.
+        #[test]
+        fn builds_without_default_features() {
+            let status = std::process::Command::new(env!("CARGO"))
+                .args(["build", "--lib", "--no-default-features", "--quiet"])
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .status()
+                .expect("could not invoke cargo");
+            assert!(status.success(), "--no-default-features build failed");
+        }
+    }
+
+    mod limit {
+        use super::*;
+
+        #[test]
+        fn too_many_constants() {
+            let mut source = String::new();
+            for i in 0..300 {
+                source.push_str(&format!("var v{i} = {}.5;\n", i));
+            }
+            expect_compile_error(&source, "Too many constants in one chunk.");
+        }
+
+        #[test]
+        fn large_loop_body_runs() {
+            // body exceeds u16::MAX bytes, forcing the long forms of both the
+            // exit jump and the backward jump
+            let mut source = String::from("var i = 0;\nwhile (i < 2) {\n    i = i + 1;\n");
+            for _ in 0..20000 {
+                source.push_str("    true and true;\n");
+            }
+            source.push_str("}\nprint i;");
+            expect_printed(&source, "2\n");
+        }
+
+        #[test]
+        fn large_if_body_runs() {
+            let mut source = String::from("if (true) {\n");
+            for _ in 0..20000 {
+                source.push_str("    true and true;\n");
+            }
+            source.push_str("    print \"then\";\n} else { print \"else\"; }\nprint \"after\";");
+            expect_printed(&source, "then\nafter\n");
+        }
+
+        #[test]
+        fn too_many_upvalues() {
+            // a single function tops out below 256 locals, so spread the
+            // captured variables over three enclosing functions
+            let mut source = String::new();
+            for (f, range) in [(0, 0..86), (1, 86..172), (2, 172..258)] {
+                source.push_str(&format!("fun f{f}() {{\n"));
+                for i in range {
+                    // a shared initializer keeps the constant pool small
+                    source.push_str(&format!("var a{i} = 0;\n"));
+                }
+            }
+            source.push_str("fun f3() {\nvar sum = 0;\n");
+            for i in 0..258 {
+                source.push_str(&format!("sum = sum + a{i};\n"));
+            }
+            source.push_str("}\nreturn f3;\n}\n}\n}\n");
+            expect_compile_error(&source, "Too many closure variables in function.");
+        }
+    }
+}
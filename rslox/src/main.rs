@@ -1,3 +1,143 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::exit;
+
+use rslox::{init_tracing, read_file, InterpretError, VM};
+use tracing::Level;
+
+const LOG_LEVEL: Level = Level::INFO;
+
+/// Strips verbosity flags from `args` and returns the level they select:
+/// `-v` is debug, `-vv` and beyond is trace, and `--trace=LEVEL` names one
+/// directly. Without flags the default is info.
+fn parse_log_level(args: &mut Vec<String>) -> Level {
+    let mut level = LOG_LEVEL;
+    args.retain(|arg| match arg.as_str() {
+        "-v" => {
+            level = Level::DEBUG;
+            false
+        }
+        s if s.starts_with("-v") && s[1..].bytes().all(|b| b == b'v') => {
+            level = Level::TRACE;
+            false
+        }
+        s if s.starts_with("--trace=") => {
+            level = match &s["--trace=".len()..] {
+                "error" => Level::ERROR,
+                "warn" => Level::WARN,
+                "info" => Level::INFO,
+                "debug" => Level::DEBUG,
+                _ => Level::TRACE,
+            };
+            false
+        }
+        _ => true,
+    });
+    level
+}
+
 fn main() {
-    println!("Hello, world!");
+    let mut args: Vec<String> = std::env::args().collect();
+    let level = parse_log_level(&mut args);
+    init_tracing(level);
+    match args.len() {
+        1 => repl(),
+        2 if args[1] == "bench" => bench(),
+        2 => run_file(&args[1]),
+        3 if args[1] == "dump" => dump_file(&args[2]),
+        3 if args[1] == "tokens" => print!("{}", rslox::token_dump(&read_file(&args[2]))),
+        _ => {
+            eprintln!(
+                "Usage: rslox [-v | -vv | --trace=LEVEL] [script | bench | dump script | tokens script]"
+            );
+            exit(64);
+        }
+    }
+}
+
+fn run_file(path: &str) {
+    let source = read_file(path);
+    let mut vm = VM::new();
+    match vm.interpret(&source) {
+        Ok(()) => {}
+        Err(InterpretError::CompileError(_)) => exit(65),
+        Err(InterpretError::RuntimeError { .. }) => exit(70),
+    }
+}
+
+/// Compiles a script and prints its bytecode without running it.
+fn dump_file(path: &str) {
+    let source = read_file(path);
+    let mut vm = VM::new();
+    match vm.dump(&source) {
+        Ok(listing) => print!("{listing}"),
+        Err(_) => exit(65),
+    }
+}
+
+fn repl() {
+    let mut vm = VM::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        // errors were already reported; keep the session alive
+        if let Some(name) = line.trim().strip_prefix(":doc ") {
+            match vm.doc_for(name.trim()) {
+                Some(doc) => println!("{doc}"),
+                None => println!("no documentation for '{}'", name.trim()),
+            }
+            continue;
+        }
+        let _ = vm.interpret(&line);
+    }
+}
+
+/// Times every `.lox` file in the repo's `test_files` directory and prints
+/// a compile/run table.
+fn bench() {
+    let dir = Path::new("../test_files");
+    let results = rslox::run_benchmarks(dir);
+    if results.is_empty() {
+        eprintln!("bench: no .lox files under {}", dir.display());
+        exit(64);
+    }
+    let width = results.iter().map(|(name, ..)| name.len()).max().unwrap();
+    println!("{:width$}  {:>12}  {:>12}", "file", "compile", "run");
+    for (name, compile_time, run_time) in results {
+        println!("{name:width$}  {compile_time:>12.2?}  {run_time:>12.2?}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn level_for(flags: &[&str]) -> (Level, usize) {
+        let mut args: Vec<String> = std::iter::once("rslox")
+            .chain(flags.iter().copied())
+            .chain(std::iter::once("script.lox"))
+            .map(String::from)
+            .collect();
+        let level = parse_log_level(&mut args);
+        (level, args.len())
+    }
+
+    #[test]
+    fn verbosity_flags_map_to_levels() {
+        assert_eq!(level_for(&[]), (Level::INFO, 2));
+        assert_eq!(level_for(&["-v"]), (Level::DEBUG, 2));
+        assert_eq!(level_for(&["-vv"]), (Level::TRACE, 2));
+        assert_eq!(level_for(&["-vvv"]), (Level::TRACE, 2));
+        assert_eq!(level_for(&["--trace=debug"]), (Level::DEBUG, 2));
+        assert_eq!(level_for(&["--trace=warn"]), (Level::WARN, 2));
+    }
 }
@@ -0,0 +1,398 @@
+//! Lexer for Lox source. Tokens borrow slices of the source string, so the
+//! scanner (and every token it produces) is tied to the lifetime of the input.
+
+use core::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    // single character
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Comma,
+    Dot,
+    Ellipsis,
+    Minus,
+    MinusMinus,
+    Plus,
+    PlusPlus,
+    Semicolon,
+    Slash,
+    Star,
+    // one or two characters
+    Bang,
+    BangEq,
+    Eq,
+    EqEq,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Shl,
+    Shr,
+    // literals
+    Ident,
+    String,
+    /// `r"..."` literal whose backslashes are kept as-is
+    RawString,
+    Number,
+    // keywords
+    And,
+    Class,
+    Do,
+    Else,
+    False,
+    For,
+    Fun,
+    If,
+    In,
+    Nil,
+    Or,
+    Print,
+    Return,
+    Static,
+    Super,
+    This,
+    Throw,
+    Try,
+    Catch,
+    True,
+    Var,
+    While,
+
+    Error,
+    Eof,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A single lexeme. `data` is the raw source slice (including quotes for
+/// string literals); `line` is 1-based.
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'src> {
+    pub kind: TokenKind,
+    pub data: &'src str,
+    pub line: u32,
+}
+
+impl<'src> Token<'src> {
+    pub fn new(kind: TokenKind, data: &'src str, line: u32) -> Self {
+        Self { kind, data, line }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            kind: TokenKind::Error,
+            data: "",
+            line: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Scanner<'src> {
+    source: &'src str,
+    /// byte offset of the start of the token currently being scanned
+    start: usize,
+    /// byte offset of the scan cursor
+    current: usize,
+    line: u32,
+    /// interior byte range of a block comment scanned immediately before the
+    /// most recent token, for doc-comment capture
+    pending_comment: Option<(usize, usize)>,
+}
+
+impl<'src> Scanner<'src> {
+    pub fn new(source: &'src str) -> Self {
+        Self {
+            source,
+            start: 0,
+            current: 0,
+            line: 1,
+            pending_comment: None,
+        }
+    }
+
+    /// The text of the block comment directly preceding the last token
+    /// returned by [`next_token`](Self::next_token), if any.
+    pub fn take_block_comment(&mut self) -> Option<&'src str> {
+        let (start, end) = self.pending_comment.take()?;
+        Some(self.source[start..end].trim())
+    }
+
+    pub fn next_token(&mut self) -> Token<'src> {
+        self.pending_comment = None;
+        self.skip_whitespace();
+        self.start = self.current;
+
+        let Some(c) = self.advance() else {
+            return self.make_token(TokenKind::Eof);
+        };
+
+        match c {
+            b'(' => self.make_token(TokenKind::LParen),
+            b')' => self.make_token(TokenKind::RParen),
+            b'{' => self.make_token(TokenKind::LBrace),
+            b'}' => self.make_token(TokenKind::RBrace),
+            b'[' => self.make_token(TokenKind::LBracket),
+            b']' => self.make_token(TokenKind::RBracket),
+            b'&' => self.make_token(TokenKind::Amp),
+            b'|' => self.make_token(TokenKind::Pipe),
+            b'^' => self.make_token(TokenKind::Caret),
+            b'~' => self.make_token(TokenKind::Tilde),
+            b',' => self.make_token(TokenKind::Comma),
+            b'.' => {
+                if self.peek() == Some(b'.') && self.peek_next() == Some(b'.') {
+                    self.advance();
+                    self.advance();
+                    self.make_token(TokenKind::Ellipsis)
+                } else {
+                    self.make_token(TokenKind::Dot)
+                }
+            }
+            b'-' => {
+                if self.matches(b'-') {
+                    self.make_token(TokenKind::MinusMinus)
+                } else {
+                    self.make_token(TokenKind::Minus)
+                }
+            }
+            b'+' => {
+                if self.matches(b'+') {
+                    self.make_token(TokenKind::PlusPlus)
+                } else {
+                    self.make_token(TokenKind::Plus)
+                }
+            }
+            b';' => self.make_token(TokenKind::Semicolon),
+            b'/' => self.make_token(TokenKind::Slash),
+            b'*' => self.make_token(TokenKind::Star),
+            b'!' => {
+                if self.matches(b'=') {
+                    self.make_token(TokenKind::BangEq)
+                } else {
+                    self.make_token(TokenKind::Bang)
+                }
+            }
+            b'=' => {
+                if self.matches(b'=') {
+                    self.make_token(TokenKind::EqEq)
+                } else {
+                    self.make_token(TokenKind::Eq)
+                }
+            }
+            b'>' => {
+                if self.matches(b'=') {
+                    self.make_token(TokenKind::GreaterEq)
+                } else if self.matches(b'>') {
+                    self.make_token(TokenKind::Shr)
+                } else {
+                    self.make_token(TokenKind::Greater)
+                }
+            }
+            b'<' => {
+                if self.matches(b'=') {
+                    self.make_token(TokenKind::LessEq)
+                } else if self.matches(b'<') {
+                    self.make_token(TokenKind::Shl)
+                } else {
+                    self.make_token(TokenKind::Less)
+                }
+            }
+            b'"' => self.string(),
+            b'r' if self.peek() == Some(b'"') => {
+                self.advance();
+                self.raw_string()
+            }
+            b'0'..=b'9' => self.number(),
+            c if c.is_ascii_alphabetic() || c == b'_' => self.ident(),
+            _ => self.error_token("Unexpected character."),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ' | b'\r' | b'\t') => {
+                    self.advance();
+                }
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.advance();
+                }
+                Some(b'/') => match self.peek_next() {
+                    // line comment
+                    Some(b'/') => {
+                        while !matches!(self.peek(), Some(b'\n') | None) {
+                            self.advance();
+                        }
+                    }
+                    // block comment
+                    Some(b'*') => {
+                        self.advance();
+                        self.advance();
+                        let body_start = self.current;
+                        loop {
+                            match self.peek() {
+                                Some(b'*') if self.peek_next() == Some(b'/') => {
+                                    self.pending_comment = Some((body_start, self.current));
+                                    self.advance();
+                                    self.advance();
+                                    break;
+                                }
+                                Some(b'\n') => {
+                                    self.line += 1;
+                                    self.advance();
+                                }
+                                Some(_) => {
+                                    self.advance();
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                    _ => return,
+                },
+                _ => return,
+            }
+        }
+    }
+
+    fn string(&mut self) -> Token<'src> {
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.advance();
+                    return self.make_token(TokenKind::String);
+                }
+                Some(b'\\') => {
+                    // skip the escaped character so an escaped quote doesn't
+                    // terminate the literal
+                    self.advance();
+                    self.advance();
+                }
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.advance();
+                }
+                Some(_) => {
+                    self.advance();
+                }
+                None => return self.error_token("Unterminated string."),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Token<'src> {
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.advance();
+        }
+        if self.peek() == Some(b'.') && matches!(self.peek_next(), Some(b'0'..=b'9')) {
+            self.advance();
+            while matches!(self.peek(), Some(b'0'..=b'9')) {
+                self.advance();
+            }
+        }
+        self.make_token(TokenKind::Number)
+    }
+
+    /// Body of an `r"..."` literal: backslashes have no special meaning, so
+    /// the literal runs to the first unescaped-quote-free `"`.
+    fn raw_string(&mut self) -> Token<'src> {
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.advance();
+                    return self.make_token(TokenKind::RawString);
+                }
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.advance();
+                }
+                Some(_) => {
+                    self.advance();
+                }
+                None => return self.error_token("Unterminated string."),
+            }
+        }
+    }
+
+    fn ident(&mut self) -> Token<'src> {
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_') {
+            self.advance();
+        }
+        self.make_token(self.ident_kind())
+    }
+
+    fn ident_kind(&self) -> TokenKind {
+        match &self.source[self.start..self.current] {
+            "and" => TokenKind::And,
+            "class" => TokenKind::Class,
+            "do" => TokenKind::Do,
+            "else" => TokenKind::Else,
+            "false" => TokenKind::False,
+            "for" => TokenKind::For,
+            "fun" => TokenKind::Fun,
+            "if" => TokenKind::If,
+            "in" => TokenKind::In,
+            "nil" => TokenKind::Nil,
+            "or" => TokenKind::Or,
+            "print" => TokenKind::Print,
+            "return" => TokenKind::Return,
+            "static" => TokenKind::Static,
+            "super" => TokenKind::Super,
+            "this" => TokenKind::This,
+            "throw" => TokenKind::Throw,
+            "try" => TokenKind::Try,
+            "catch" => TokenKind::Catch,
+            "true" => TokenKind::True,
+            "var" => TokenKind::Var,
+            "while" => TokenKind::While,
+            _ => TokenKind::Ident,
+        }
+    }
+
+    fn make_token(&self, kind: TokenKind) -> Token<'src> {
+        Token::new(kind, &self.source[self.start..self.current], self.line)
+    }
+
+    fn error_token(&self, msg: &'static str) -> Token<'src> {
+        Token::new(TokenKind::Error, msg, self.line)
+    }
+
+    fn advance(&mut self) -> Option<u8> {
+        let c = self.source.as_bytes().get(self.current).copied();
+        if c.is_some() {
+            self.current += 1;
+        }
+        c
+    }
+
+    fn matches(&mut self, expected: u8) -> bool {
+        if self.peek() == Some(expected) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.current).copied()
+    }
+
+    fn peek_next(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.current + 1).copied()
+    }
+}
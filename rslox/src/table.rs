@@ -0,0 +1,519 @@
+//! Open-addressing hash table keyed by interned strings, used for globals,
+//! instance fields, class method tables, and the VM's string-intern set.
+//! Deliberately mirrors the clox table: power-of-two capacity, linear probing,
+//! tombstones, and a 75% load factor.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use core::fmt::Display;
+
+use crate::value::{LoxStr, Value};
+
+const MAX_LOAD_NUM: usize = 3;
+const MAX_LOAD_DEN: usize = 4;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) enum Entry {
+    #[default]
+    Empty,
+    Tombstone,
+    Full {
+        key: LoxStr,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    /// number of `Full` entries
+    count: usize,
+    /// `Full` plus tombstone entries, used for the load-factor check
+    filled: usize,
+    entries: Box<[Entry]>,
+}
+
+pub(crate) fn hash_str(s: &str) -> u64 {
+    hash_bytes(s.as_bytes())
+}
+
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the slot that `key` occupies or should occupy: either the
+    /// matching `Full` entry, the first tombstone passed on the way, or the
+    /// terminating `Empty` slot.
+    fn find_idx(&self, key: &str) -> usize {
+        debug_assert!(!self.entries.is_empty());
+        let mask = self.entries.len() - 1;
+        let mut idx = (hash_str(key) as usize) & mask;
+        let mut tombstone: Option<usize> = None;
+        loop {
+            match &self.entries[idx] {
+                Entry::Empty => return tombstone.unwrap_or(idx),
+                Entry::Tombstone => {
+                    if tombstone.is_none() {
+                        tombstone = Some(idx);
+                    }
+                }
+                Entry::Full { key: k, .. } => {
+                    // interned keys are usually pointer-identical, but fall
+                    // back to a content compare so lookups by a plain &str
+                    // (e.g. during interning itself) also work. The pointer
+                    // check must also match lengths: a slice of an interned
+                    // string shares its data pointer with the original.
+                    if (core::ptr::eq(k.as_ptr(), key.as_ptr()) && k.len() == key.len())
+                        || **k == *key
+                    {
+                        return idx;
+                    }
+                }
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        if self.count == 0 {
+            return None;
+        }
+        match &self.entries[self.find_idx(key)] {
+            Entry::Full { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the interned key equal to `key`, if present. This is the core
+    /// of string interning: the caller hands any `&str` and gets back the
+    /// canonical `LoxStr`.
+    pub fn get_key(&self, key: &str) -> Option<LoxStr> {
+        if self.count == 0 {
+            return None;
+        }
+        match &self.entries[self.find_idx(key)] {
+            Entry::Full { key: k, .. } => Some(Rc::clone(k)),
+            _ => None,
+        }
+    }
+
+    /// Inserts or overwrites. Returns `true` if the key was not already
+    /// present.
+    pub fn set(&mut self, key: LoxStr, value: Value) -> bool {
+        if (self.filled + 1) * MAX_LOAD_DEN > self.entries.len() * MAX_LOAD_NUM {
+            self.grow();
+        }
+        let idx = self.find_idx(&key);
+        let is_new = match self.entries[idx] {
+            Entry::Empty => {
+                self.filled += 1;
+                true
+            }
+            Entry::Tombstone => true,
+            Entry::Full { .. } => false,
+        };
+        if is_new {
+            self.count += 1;
+        }
+        self.entries[idx] = Entry::Full { key, value };
+        is_new
+    }
+
+    /// Removes `key`, leaving a tombstone. Returns `true` if it was present.
+    pub fn delete(&mut self, key: &str) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let idx = self.find_idx(key);
+        if matches!(self.entries[idx], Entry::Full { .. }) {
+            self.entries[idx] = Entry::Tombstone;
+            self.count -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&LoxStr, &Value)> {
+        self.entries.iter().filter_map(|e| match e {
+            Entry::Full { key, value } => Some((key, value)),
+            _ => None,
+        })
+    }
+
+    /// Slot currently occupied by `key`, for the VM's global inline caches.
+    pub(crate) fn slot_of(&self, key: &str) -> Option<usize> {
+        if self.count == 0 {
+            return None;
+        }
+        let idx = self.find_idx(key);
+        matches!(self.entries[idx], Entry::Full { .. }).then_some(idx)
+    }
+
+    /// Cache-validated read: the value in `slot`, provided the slot still
+    /// holds exactly `key` (pointer identity, so a rehash or deletion since
+    /// the slot was cached reads as a miss).
+    pub(crate) fn get_at(&self, slot: usize, key: &LoxStr) -> Option<&Value> {
+        match self.entries.get(slot) {
+            Some(Entry::Full { key: k, value }) if Rc::ptr_eq(k, key) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Cache-validated overwrite of an existing entry; `false` means the
+    /// cache missed and the caller must take the probing path.
+    pub(crate) fn set_at(&mut self, slot: usize, key: &LoxStr, value: Value) -> bool {
+        match self.entries.get_mut(slot) {
+            Some(Entry::Full { key: k, value: v }) if Rc::ptr_eq(k, key) => {
+                *v = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops every entry whose key should no longer be considered live,
+    /// according to `dead`. Used by the GC to sweep the intern set.
+    pub(crate) fn retain_keys(&mut self, mut live: impl FnMut(&LoxStr) -> bool) {
+        for entry in self.entries.iter_mut() {
+            if let Entry::Full { key, .. } = entry {
+                if !live(key) {
+                    *entry = Entry::Tombstone;
+                    self.count -= 1;
+                }
+            }
+        }
+    }
+
+    fn grow(&mut self) {
+        let new_cap = if self.entries.is_empty() {
+            8
+        } else {
+            self.entries.len() * 2
+        };
+        self.rehash(new_cap);
+    }
+
+    /// Halves capacity (rehashing away tombstones) when live entries drop
+    /// below 25% of it. Called after GC sweeps the intern set so a burst of
+    /// short-lived strings doesn't pin a huge table.
+    pub(crate) fn maybe_shrink(&mut self) {
+        let cap = self.entries.len();
+        if cap > 8 && self.count * 4 < cap {
+            self.rehash((cap / 2).max(8));
+        }
+    }
+
+    fn rehash(&mut self, new_cap: usize) {
+        debug_assert!(new_cap.is_power_of_two());
+        let old = core::mem::replace(&mut self.entries, vec![Entry::Empty; new_cap].into());
+        self.count = 0;
+        self.filled = 0;
+        for entry in old {
+            if let Entry::Full { key, value } = entry {
+                let idx = self.find_idx(&key);
+                self.entries[idx] = Entry::Full { key, value };
+                self.count += 1;
+                self.filled += 1;
+            }
+        }
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{{ ")?;
+        let mut first = true;
+        for (key, value) in self.iter() {
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(f, "{key}: {value}")?;
+        }
+        write!(f, " }}")
+    }
+}
+
+/// Open-addressing table keyed by arbitrary hashable [`Value`]s (nil, bool,
+/// number, string) via [`Value::content_hash`], with equality from
+/// `Value::PartialEq`. A parallel type rather than a generalization of
+/// [`Table`] so the string-interning fast path stays untouched; probing,
+/// tombstones, and the load factor mirror it.
+#[derive(Debug, Clone, Default)]
+pub struct ValueTable {
+    count: usize,
+    filled: usize,
+    entries: Box<[ValueEntry]>,
+}
+
+#[derive(Debug, Clone, Default)]
+enum ValueEntry {
+    #[default]
+    Empty,
+    Tombstone,
+    Full {
+        key: Value,
+        value: Value,
+    },
+}
+
+fn value_hash(key: &Value) -> u64 {
+    key.content_hash()
+        .expect("ValueTable keys must be nil, bool, number, or string")
+}
+
+impl ValueTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn find_idx(&self, key: &Value) -> usize {
+        debug_assert!(!self.entries.is_empty());
+        let mask = self.entries.len() - 1;
+        let mut idx = (value_hash(key) as usize) & mask;
+        let mut tombstone: Option<usize> = None;
+        loop {
+            match &self.entries[idx] {
+                ValueEntry::Empty => return tombstone.unwrap_or(idx),
+                ValueEntry::Tombstone => {
+                    if tombstone.is_none() {
+                        tombstone = Some(idx);
+                    }
+                }
+                ValueEntry::Full { key: k, .. } => {
+                    if k == key {
+                        return idx;
+                    }
+                }
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        if self.count == 0 {
+            return None;
+        }
+        match &self.entries[self.find_idx(key)] {
+            ValueEntry::Full { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Inserts or overwrites. Returns `true` if the key was not already
+    /// present.
+    pub fn set(&mut self, key: Value, value: Value) -> bool {
+        if (self.filled + 1) * MAX_LOAD_DEN > self.entries.len() * MAX_LOAD_NUM {
+            self.grow();
+        }
+        let idx = self.find_idx(&key);
+        let is_new = match self.entries[idx] {
+            ValueEntry::Empty => {
+                self.filled += 1;
+                true
+            }
+            ValueEntry::Tombstone => true,
+            ValueEntry::Full { .. } => false,
+        };
+        if is_new {
+            self.count += 1;
+        }
+        self.entries[idx] = ValueEntry::Full { key, value };
+        is_new
+    }
+
+    /// Removes `key`, leaving a tombstone. Returns `true` if it was present.
+    pub fn delete(&mut self, key: &Value) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let idx = self.find_idx(key);
+        if matches!(self.entries[idx], ValueEntry::Full { .. }) {
+            self.entries[idx] = ValueEntry::Tombstone;
+            self.count -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter().filter_map(|e| match e {
+            ValueEntry::Full { key, value } => Some((key, value)),
+            _ => None,
+        })
+    }
+
+    fn grow(&mut self) {
+        let new_cap = if self.entries.is_empty() {
+            8
+        } else {
+            self.entries.len() * 2
+        };
+        self.rehash(new_cap);
+    }
+
+    fn rehash(&mut self, new_cap: usize) {
+        debug_assert!(new_cap.is_power_of_two());
+        let old = core::mem::replace(&mut self.entries, vec![ValueEntry::Empty; new_cap].into());
+        self.count = 0;
+        self.filled = 0;
+        for entry in old {
+            if let ValueEntry::Full { key, value } = entry {
+                let idx = self.find_idx(&key);
+                self.entries[idx] = ValueEntry::Full { key, value };
+                self.count += 1;
+                self.filled += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(s: &str) -> LoxStr {
+        Rc::from(s)
+    }
+
+    #[test]
+    fn set_get_delete() {
+        let mut table = Table::new();
+        assert!(table.set(key("a"), Value::Float(1.0)));
+        assert!(table.set(key("b"), Value::Float(2.0)));
+        assert!(!table.set(key("a"), Value::Float(3.0)));
+        assert_eq!(table.get("a"), Some(&Value::Float(3.0)));
+        assert_eq!(table.get("b"), Some(&Value::Float(2.0)));
+        assert!(table.delete("a"));
+        assert!(!table.delete("a"));
+        assert_eq!(table.get("a"), None);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn grows_past_load_factor() {
+        let mut table = Table::new();
+        for i in 0..100 {
+            table.set(key(&format!("key{i}")), Value::Float(i as f64));
+        }
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&format!("key{i}")), Some(&Value::Float(i as f64)));
+        }
+    }
+
+    #[test]
+    fn tombstones_dont_break_probing() {
+        let mut table = Table::new();
+        for i in 0..20 {
+            table.set(key(&format!("k{i}")), Value::Nil);
+        }
+        for i in 0..10 {
+            table.delete(&format!("k{i}"));
+        }
+        for i in 10..20 {
+            assert!(table.get(&format!("k{i}")).is_some());
+        }
+    }
+
+    #[test]
+    fn display_skips_dead_slots() {
+        let mut table = Table::new();
+        table.set(key("a"), Value::Float(1.0));
+        table.set(key("b"), Value::Float(2.0));
+        table.set(key("gone"), Value::Nil);
+        table.delete("gone");
+        let text = format!("{table}");
+        // iteration order follows hash slots, so check parts rather than the
+        // exact string
+        assert!(text.starts_with("{ ") && text.ends_with(" }"), "{text}");
+        assert!(text.contains("a: 1"), "{text}");
+        assert!(text.contains("b: 2"), "{text}");
+        assert!(!text.contains("gone"), "{text}");
+    }
+
+    #[test]
+    fn shrinks_when_mostly_empty() {
+        let mut table = Table::new();
+        for i in 0..100 {
+            table.set(key(&format!("key{i}")), Value::Float(i as f64));
+        }
+        let grown = table.capacity();
+        for i in 0..95 {
+            table.delete(&format!("key{i}"));
+        }
+        table.maybe_shrink();
+        assert!(table.capacity() < grown, "capacity {} did not shrink", table.capacity());
+        for i in 95..100 {
+            assert_eq!(table.get(&format!("key{i}")), Some(&Value::Float(i as f64)));
+        }
+    }
+
+    #[test]
+    fn value_table_mixed_keys() {
+        let mut table = ValueTable::new();
+        assert!(table.set(Value::Float(1.5), Value::Float(10.0)));
+        assert!(table.set(Value::Bool(true), Value::Float(20.0)));
+        assert!(table.set(Value::Nil, Value::Float(30.0)));
+        assert!(!table.set(Value::Float(1.5), Value::Float(40.0)));
+        assert_eq!(table.get(&Value::Float(1.5)), Some(&Value::Float(40.0)));
+        assert_eq!(table.get(&Value::Bool(true)), Some(&Value::Float(20.0)));
+        assert_eq!(table.get(&Value::Nil), Some(&Value::Float(30.0)));
+        assert_eq!(table.get(&Value::Bool(false)), None);
+        assert!(table.delete(&Value::Bool(true)));
+        assert_eq!(table.get(&Value::Bool(true)), None);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn value_table_grows_past_load_factor() {
+        let mut table = ValueTable::new();
+        for i in 0..100 {
+            table.set(Value::Float(i as f64), Value::Float(-(i as f64)));
+        }
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(
+                table.get(&Value::Float(i as f64)),
+                Some(&Value::Float(-(i as f64)))
+            );
+        }
+    }
+}
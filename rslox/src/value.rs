@@ -0,0 +1,538 @@
+//! Runtime values and heap object types. Heap values are reference counted;
+//! the VM additionally tracks them in its heap-object list for allocation
+//! accounting and sweeping (see `vm`).
+
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt::{Debug, Display};
+
+use strum::VariantNames;
+
+use crate::chunk::Chunk;
+use crate::table::Table;
+use crate::vm::GCStats;
+
+/// `f64::fract` without `std`: the remainder operator lives in core and
+/// agrees with `fract` on every input, including `NaN` for non-finite ones.
+pub(crate) fn fract(v: f64) -> f64 {
+    v % 1.0
+}
+
+/// Interned string handle. Equality of interned strings is pointer equality;
+/// `Value::PartialEq` falls back to a content compare for safety.
+pub type LoxStr = Rc<str>;
+
+/// Mutable list handle. Lists compare by identity, like the other mutable
+/// heap objects.
+pub type LoxList = Rc<RefCell<Vec<Value>>>;
+
+#[derive(Debug, Clone, VariantNames)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Float(f64),
+    String(LoxStr),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    NativeFn(Native),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
+    List(LoxList),
+    /// non-owning instance handle from `weakref()`; `deref()` yields the
+    /// instance while it is strongly reachable elsewhere, `nil` afterwards
+    Weak(alloc::rc::Weak<Instance>),
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: Option<LoxStr>,
+    pub arg_count: u8,
+    pub upval_count: usize,
+    /// declared without a parameter list; `ReadProperty` invokes it instead
+    /// of binding it
+    pub is_getter: bool,
+    /// the last parameter is a `...rest` list collecting surplus arguments
+    pub is_variadic: bool,
+    /// declared parameter names, in order, for arity diagnostics
+    pub params: Vec<LoxStr>,
+    /// block comment immediately preceding the declaration, for `:doc`
+    pub doc: Option<Rc<str>>,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn new(name: Option<LoxStr>, source: Rc<str>) -> Self {
+        Self {
+            name,
+            arg_count: 0,
+            upval_count: 0,
+            is_getter: false,
+            is_variadic: false,
+            params: Vec::new(),
+            doc: None,
+            chunk: Chunk::new(source),
+        }
+    }
+
+    pub fn name_str(&self) -> &str {
+        self.name.as_deref().unwrap_or("script")
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable. `Open` holds the stack slot while the variable is
+/// still live on the stack; `Closed` owns the value after the slot is popped.
+#[derive(Debug, Clone)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// Signature shared by all natives: they receive the VM (for allocation,
+/// interning, and introspection) and their arguments, and may fail with a
+/// runtime-error message.
+pub type NativeFunc = fn(&mut crate::vm::VM, &[Value]) -> Result<Value, String>;
+
+#[derive(Clone, Copy)]
+pub struct Native {
+    pub name: &'static str,
+    pub func: NativeFunc,
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: LoxStr,
+    pub methods: RefCell<Table>,
+    /// methods callable on the class itself rather than instances
+    pub statics: RefCell<Table>,
+    /// set by `Inherit`; method lookup walks this chain, and it backs
+    /// `is()` and `superclass()` reflection
+    pub superclass: RefCell<Option<Rc<Class>>>,
+}
+
+impl Class {
+    pub fn new(name: LoxStr) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(Table::new()),
+            statics: RefCell::new(Table::new()),
+            superclass: RefCell::new(None),
+        }
+    }
+
+    /// Resolves `name` against this class's own methods, then up the
+    /// superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Value> {
+        if let Some(method) = self.methods.borrow().get(name) {
+            return Some(method.clone());
+        }
+        self.superclass
+            .borrow()
+            .as_ref()
+            .and_then(|superclass| superclass.find_method(name))
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<Table>,
+    /// set once the GC has run this instance's `finalize` method, so a
+    /// resurrected object is never finalized twice
+    pub finalized: Cell<bool>,
+    /// set by the `freeze()` native; frozen instances reject field writes
+    pub frozen: Cell<bool>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(Table::new()),
+            finalized: Cell::new(false),
+            frozen: Cell::new(false),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Rough byte size for GC accounting.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::Nil | Value::Bool(_) | Value::Float(_) | Value::NativeFn(_)
+            | Value::Weak(_) => core::mem::size_of::<Value>(),
+            Value::String(s) => core::mem::size_of::<Value>() + s.len(),
+            Value::Function(f) => core::mem::size_of::<Function>() + f.chunk.data.len(),
+            Value::Closure(c) => {
+                core::mem::size_of::<Closure>()
+                    + c.upvalues.len() * core::mem::size_of::<Rc<RefCell<Upvalue>>>()
+            }
+            Value::Class(_) => core::mem::size_of::<Class>(),
+            Value::Instance(i) => {
+                core::mem::size_of::<Instance>()
+                    + i.fields.borrow().capacity() * core::mem::size_of::<(LoxStr, Value)>()
+            }
+            Value::BoundMethod(_) => core::mem::size_of::<BoundMethod>(),
+            Value::List(l) => {
+                core::mem::size_of::<Vec<Value>>()
+                    + l.borrow().capacity() * core::mem::size_of::<Value>()
+            }
+        }
+    }
+
+    /// Content hash for the variants usable as map keys, or `None` for the
+    /// rest. Consistent with `PartialEq`: interned strings hash by pointer,
+    /// and `-0.0` is normalized to `0.0` so the two equal floats collide.
+    pub fn content_hash(&self) -> Option<u64> {
+        match self {
+            Value::Nil => Some(crate::table::hash_bytes(&[0])),
+            Value::Bool(b) => Some(crate::table::hash_bytes(&[1, *b as u8])),
+            Value::Float(f) => {
+                let f = if *f == 0.0 { 0.0 } else { *f };
+                Some(crate::table::hash_bytes(&f.to_le_bytes()))
+            }
+            Value::String(s) => {
+                Some(crate::table::hash_bytes(&(Rc::as_ptr(s) as *const u8 as usize).to_le_bytes()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of strong references to the underlying heap allocation, or
+    /// `None` for unboxed values.
+    pub(crate) fn refcount(&self) -> Option<usize> {
+        match self {
+            Value::String(s) => Some(Rc::strong_count(s)),
+            Value::Function(f) => Some(Rc::strong_count(f)),
+            Value::Closure(c) => Some(Rc::strong_count(c)),
+            Value::Class(c) => Some(Rc::strong_count(c)),
+            Value::Instance(i) => Some(Rc::strong_count(i)),
+            Value::BoundMethod(b) => Some(Rc::strong_count(b)),
+            Value::List(l) => Some(Rc::strong_count(l)),
+            _ => None,
+        }
+    }
+
+    pub fn add(self, rhs: Value, strings: &mut Table, objects: &mut Vec<Value>, stats: &mut GCStats) -> Result<Value, String> {
+        match (&self, &rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
+            (Value::String(a), Value::String(b)) => {
+                let mut buf = String::with_capacity(a.len() + b.len());
+                buf.push_str(a);
+                buf.push_str(b);
+                Ok(Value::String(alloc_str(&buf, strings, objects, stats)))
+            }
+            _ => Err("Operands must be two numbers or two strings.".to_string()),
+        }
+    }
+
+    /// Integer view of a value for the bitwise operators: only whole floats
+    /// convert.
+    fn as_int(&self) -> Result<i64, String> {
+        match self {
+            Value::Float(f) if fract(*f) == 0.0 && *f >= i64::MIN as f64 && *f <= i64::MAX as f64 => {
+                Ok(*f as i64)
+            }
+            _ => Err("Operands must be whole numbers.".to_string()),
+        }
+    }
+
+    pub fn bit_and(self, rhs: Value) -> Result<Value, String> {
+        Ok(Value::Float((self.as_int()? & rhs.as_int()?) as f64))
+    }
+
+    pub fn bit_or(self, rhs: Value) -> Result<Value, String> {
+        Ok(Value::Float((self.as_int()? | rhs.as_int()?) as f64))
+    }
+
+    pub fn bit_xor(self, rhs: Value) -> Result<Value, String> {
+        Ok(Value::Float((self.as_int()? ^ rhs.as_int()?) as f64))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn shl(self, rhs: Value) -> Result<Value, String> {
+        let count = u32::try_from(rhs.as_int()?).ok().filter(|c| *c < 64);
+        let Some(count) = count else {
+            return Err("Shift count out of range.".to_string());
+        };
+        Ok(Value::Float((self.as_int()? << count) as f64))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn shr(self, rhs: Value) -> Result<Value, String> {
+        let count = u32::try_from(rhs.as_int()?).ok().filter(|c| *c < 64);
+        let Some(count) = count else {
+            return Err("Shift count out of range.".to_string());
+        };
+        Ok(Value::Float((self.as_int()? >> count) as f64))
+    }
+
+    pub fn bit_not(self) -> Result<Value, String> {
+        Ok(Value::Float(!self.as_int()? as f64))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn sub(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a - b)),
+            _ => Err("Operands must be numbers.".to_string()),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn mul(self, rhs: Value, strings: &mut Table, objects: &mut Vec<Value>, stats: &mut GCStats) -> Result<Value, String> {
+        match (&self, &rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a * b)),
+            (Value::String(s), Value::Float(n)) | (Value::Float(n), Value::String(s)) => {
+                if fract(*n) != 0.0 || *n < 0.0 {
+                    return Err("String repeat count must be a non-negative whole number.".to_string());
+                }
+                Ok(Value::String(alloc_str(&s.repeat(*n as usize), strings, objects, stats)))
+            }
+            _ => Err("Operands must be numbers.".to_string()),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn div(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a / b)),
+            _ => Err("Operands must be numbers.".to_string()),
+        }
+    }
+
+    pub fn less(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Bool(a < b)),
+            (Value::String(a), Value::String(b)) => Ok(Value::Bool(*a < *b)),
+            _ => Err("Operands must be two numbers or two strings.".to_string()),
+        }
+    }
+
+    pub fn greater(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Bool(a > b)),
+            (Value::String(a), Value::String(b)) => Ok(Value::Bool(*a > *b)),
+            _ => Err("Operands must be two numbers or two strings.".to_string()),
+        }
+    }
+
+    /// Cross-type ordering used when [`VMConfig::total_ordering`] is set:
+    /// `nil < booleans < numbers < strings`, with the usual order inside
+    /// each group. `None` for types with no defined order, and for `NaN`.
+    ///
+    /// [`VMConfig::total_ordering`]: crate::vm::VMConfig::total_ordering
+    pub fn total_cmp(&self, rhs: &Value) -> Option<core::cmp::Ordering> {
+        fn rank(v: &Value) -> Option<u8> {
+            Some(match v {
+                Value::Nil => 0,
+                Value::Bool(_) => 1,
+                Value::Float(_) => 2,
+                Value::String(_) => 3,
+                _ => return None,
+            })
+        }
+        let (ra, rb) = (rank(self)?, rank(rhs)?);
+        if ra != rb {
+            return Some(ra.cmp(&rb));
+        }
+        match (self, rhs) {
+            (Value::Nil, Value::Nil) => Some(core::cmp::Ordering::Equal),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => Some(a.as_ref().cmp(b.as_ref())),
+            _ => unreachable!("equal ranks for different types"),
+        }
+    }
+
+    pub fn negate(self) -> Result<Value, String> {
+        match self {
+            Value::Float(a) => Ok(Value::Float(-a)),
+            _ => Err("Operand must be a number.".to_string()),
+        }
+    }
+}
+
+/// Interns `s`, registering a newly allocated string with the heap-object
+/// list and allocation stats. Returns the canonical handle either way.
+pub(crate) fn alloc_str(
+    s: &str,
+    strings: &mut Table,
+    objects: &mut Vec<Value>,
+    stats: &mut GCStats,
+) -> LoxStr {
+    if let Some(interned) = strings.get_key(s) {
+        return interned;
+    }
+    let interned: LoxStr = Rc::from(s);
+    strings.set(Rc::clone(&interned), Value::Nil);
+    let value = Value::String(Rc::clone(&interned));
+    stats.bytes_allocated += value.size();
+    objects.push(value);
+    interned
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            // interned strings are pointer-equal; the content compare covers
+            // strings created outside the intern table
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b) || a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFn(a), Value::NativeFn(b)) => core::ptr::fn_addr_eq(a.func, b.func),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Weak(a), Value::Weak(b)) => a.ptr_eq(b),
+            _ => false,
+        }
+    }
+}
+
+fn fmt_float(f: &mut core::fmt::Formatter<'_>, v: f64) -> core::fmt::Result {
+    if fract(v) == 0.0 && v.is_finite() && v.abs() < 1e15 {
+        write!(f, "{}", v as i64)
+    } else {
+        write!(f, "{v}")
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.fmt_with_seen(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    /// `Display` body. `seen` holds the instances and lists currently being
+    /// formatted further up the recursion, so a self-referential object
+    /// prints `<cycle>` instead of overflowing the stack.
+    fn fmt_with_seen(
+        &self,
+        f: &mut core::fmt::Formatter<'_>,
+        seen: &mut Vec<*const ()>,
+    ) -> core::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Float(v) => fmt_float(f, *v),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Function(func) => write!(f, "<fn {}>", func.name_str()),
+            Value::Closure(c) => write!(f, "<fn {}>", c.function.name_str()),
+            Value::NativeFn(n) => write!(f, "<native fn {}>", n.name),
+            Value::Class(c) => write!(f, "{}", c.name),
+            Value::Instance(i) => {
+                let ptr = Rc::as_ptr(i).cast::<()>();
+                if seen.contains(&ptr) {
+                    return write!(f, "<cycle>");
+                }
+                seen.push(ptr);
+                write!(f, "{}{{", i.class.name)?;
+                let fields = i.fields.borrow();
+                let mut first = true;
+                for (key, value) in fields.iter() {
+                    if !first {
+                        write!(f, ",")?;
+                    }
+                    first = false;
+                    write!(f, " {key}: ")?;
+                    value.fmt_with_seen(f, seen)?;
+                }
+                write!(f, " }}")?;
+                seen.pop();
+                Ok(())
+            }
+            Value::BoundMethod(b) => write!(f, "<fn {}>", b.method.function.name_str()),
+            Value::Weak(weak) => {
+                if weak.strong_count() == 0 {
+                    write!(f, "<dead weakref>")
+                } else {
+                    write!(f, "<weakref>")
+                }
+            }
+            Value::List(l) => {
+                let ptr = Rc::as_ptr(l).cast::<()>();
+                if seen.contains(&ptr) {
+                    return write!(f, "<cycle>");
+                }
+                seen.push(ptr);
+                write!(f, "[")?;
+                for (i, item) in l.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_with_seen(f, seen)?;
+                }
+                write!(f, "]")?;
+                seen.pop();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn equal_values_hash_equally() {
+        assert_eq!(Value::Nil.content_hash(), Value::Nil.content_hash());
+        assert_eq!(
+            Value::Bool(true).content_hash(),
+            Value::Bool(true).content_hash()
+        );
+        assert_ne!(
+            Value::Bool(true).content_hash(),
+            Value::Bool(false).content_hash()
+        );
+        assert_eq!(
+            Value::Float(42.0).content_hash(),
+            Value::Float(42.0).content_hash()
+        );
+        assert_ne!(Value::Nil.content_hash(), None);
+    }
+
+    #[test]
+    fn zero_and_negative_zero_agree() {
+        let pos = Value::Float(0.0);
+        let neg = Value::Float(-0.0);
+        assert_eq!(pos, neg);
+        assert_eq!(pos.content_hash(), neg.content_hash());
+    }
+
+    #[test]
+    fn unhashable_variants_return_none() {
+        let list: LoxList = Rc::new(RefCell::new(vec![]));
+        assert_eq!(Value::List(list).content_hash(), None);
+    }
+}
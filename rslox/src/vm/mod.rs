@@ -0,0 +1,1838 @@
+//! The bytecode interpreter: value stack, call frames, globals, and the
+//! heap-object list used for allocation accounting and sweeping.
+
+mod natives;
+#[cfg(test)]
+mod test;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::vec;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::Display;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::chunk::{Chunk, OpCode, N_OPCODES};
+use crate::compiler;
+use crate::table::Table;
+use crate::value::{
+    alloc_str, fract, BoundMethod, Closure, Function, Instance, LoxStr, Upvalue, Value,
+};
+
+pub const MAX_FRAMES: usize = 64;
+pub const MAX_STACK: usize = MAX_FRAMES * 256;
+
+/// Heap growth factor: after a collection the next GC triggers at
+/// `bytes_allocated * GC_HEAP_GROW_FACTOR`.
+pub const GC_HEAP_GROW_FACTOR: usize = 2;
+const INITIAL_GC_THRESHOLD: usize = 1024 * 1024;
+
+/// Host-tunable VM limits. The value stack grows on demand but tops out at
+/// [`MAX_STACK`] slots, which bounds usable `max_frames` for frames with
+/// many locals.
+#[derive(Debug, Clone, Copy)]
+pub struct VMConfig {
+    pub max_frames: usize,
+    pub initial_gc_threshold: usize,
+    pub gc_grow_factor: usize,
+    /// when set, `+` coerces a number/bool/nil operand to its display string
+    /// if the other operand is a string (off by default: the Lox reference
+    /// disallows it)
+    pub string_coercion: bool,
+    /// when set, `<`/`>` fall back to the cross-type total order
+    /// `nil < booleans < numbers < strings` instead of erroring (off by
+    /// default: the Lox reference only compares numbers)
+    pub total_ordering: bool,
+}
+
+impl Default for VMConfig {
+    fn default() -> Self {
+        Self {
+            max_frames: MAX_FRAMES,
+            initial_gc_threshold: INITIAL_GC_THRESHOLD,
+            gc_grow_factor: GC_HEAP_GROW_FACTOR,
+            string_coercion: false,
+            total_ordering: false,
+        }
+    }
+}
+
+/// Result of a bounded execution slice: either the script finished or its
+/// instruction budget ran out first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VMState {
+    Done,
+    Yielded,
+}
+
+/// Coarse category of a runtime error, so embedders can branch without
+/// string-matching the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    UndefinedVariable,
+    UndefinedProperty,
+    TypeMismatch,
+    Arity,
+    StackOverflow,
+    IndexOutOfRange,
+    UncaughtThrow,
+    Other,
+}
+
+impl ErrorKind {
+    /// Derives the category from the message. Every runtime message is
+    /// produced by this module, so the prefixes here are exhaustive for the
+    /// kinds we distinguish.
+    fn classify(msg: &str) -> Self {
+        if msg.starts_with("Undefined variable") {
+            ErrorKind::UndefinedVariable
+        } else if msg.starts_with("Undefined property") {
+            ErrorKind::UndefinedProperty
+        } else if msg == "Stack overflow." {
+            ErrorKind::StackOverflow
+        } else if msg.starts_with("Uncaught error") {
+            ErrorKind::UncaughtThrow
+        } else if msg.contains("out of range") {
+            ErrorKind::IndexOutOfRange
+        } else if msg.contains(" args, got ") || msg.contains("more than 255 arguments") {
+            ErrorKind::Arity
+        } else if msg.starts_with("Operands must")
+            || msg.starts_with("Operand must")
+            || msg.starts_with("Can only")
+            || msg.starts_with("Cannot ")
+            || msg.contains("expects a")
+            || msg.contains("expects two")
+            || msg.contains("expects number")
+            || msg.contains("must be a number")
+        {
+            ErrorKind::TypeMismatch
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InterpretError {
+    CompileError(String),
+    RuntimeError {
+        msg: String,
+        /// category for programmatic consumers; `Display` stays message-only
+        kind: ErrorKind,
+        /// source line the error was raised on, when a frame was live
+        line: Option<u32>,
+        /// `[line N] in <name>` frames captured when the error was raised,
+        /// innermost first; `None` when no frames were live
+        trace: Option<String>,
+    },
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InterpretError::CompileError(msg) | InterpretError::RuntimeError { msg, .. } => {
+                write!(f, "{msg}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for InterpretError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GCStats {
+    pub bytes_allocated: usize,
+    pub next_gc: usize,
+}
+
+impl Default for GCStats {
+    fn default() -> Self {
+        Self {
+            bytes_allocated: 0,
+            next_gc: INITIAL_GC_THRESHOLD,
+        }
+    }
+}
+
+/// Growable value stack. `cursor` points one past the top element; `data`
+/// holds the high-water mark so slots above the cursor stay allocated (and
+/// nil) for reuse. Upvalues refer to slots by index, so growth reallocating
+/// the backing storage is safe.
+pub(crate) struct Stack {
+    data: Vec<Value>,
+    pub cursor: usize,
+}
+
+impl Stack {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), String> {
+        if self.cursor == MAX_STACK {
+            return Err("Stack overflow.".to_string());
+        }
+        if self.cursor == self.data.len() {
+            self.data.push(value);
+        } else {
+            self.data[self.cursor] = value;
+        }
+        self.cursor += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Value {
+        debug_assert!(self.cursor > 0, "popped an empty stack");
+        self.cursor -= 1;
+        core::mem::replace(&mut self.data[self.cursor], Value::Nil)
+    }
+
+    fn top(&self) -> &Value {
+        &self.data[self.cursor - 1]
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.data[self.cursor - 1 - distance]
+    }
+
+    fn get(&self, slot: usize) -> &Value {
+        &self.data[slot]
+    }
+
+    fn set(&mut self, slot: usize, value: Value) {
+        self.data[slot] = value;
+    }
+
+    fn truncate(&mut self, to: usize) {
+        for slot in &mut self.data[to..self.cursor] {
+            *slot = Value::Nil;
+        }
+        self.cursor = to;
+    }
+}
+
+/// A `try` region: where execution resumes when a runtime error is caught.
+struct Handler {
+    /// `frames.len()` when the handler was installed
+    frame_depth: usize,
+    /// value-stack cursor to unwind to; the caught error is pushed on top
+    stack_cursor: usize,
+    /// instruction offset of the catch block, in the installing frame
+    catch_ip: usize,
+}
+
+pub(crate) struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// stack slot of the callee; locals index from here
+    sp: usize,
+}
+
+pub struct VM {
+    config: VMConfig,
+    pub(crate) stack: Stack,
+    frames: Vec<CallFrame>,
+    globals: Table,
+    /// monomorphic inline cache for `ReadGlobal`/`WriteGlobal`, indexed by
+    /// the opcode's constant operand: the `globals` slot the name resolved
+    /// to last time. Validated by key identity on every hit, so rehashes and
+    /// redefinitions degrade to a normal probe instead of misbehaving.
+    global_cache: Vec<Option<usize>>,
+    /// like `global_cache`, but for `ReadProperty`/`WriteProperty`: the
+    /// fields-table slot the name last hit. Instances of one class built the
+    /// same way place fields in the same slots, so the cache is effectively
+    /// per shape; the identity check on every hit keeps divergent instances
+    /// (or a rehash after adding fields) correct via the slow path.
+    property_cache: Vec<Option<usize>>,
+    strings: Table,
+    heap_objects: Vec<Value>,
+    gc_stats: GCStats,
+    /// set while `collect_garbage` runs; finalizers re-enter the VM and
+    /// must not trigger a nested collection
+    collecting: bool,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    /// payload of an in-flight `throw`, bound by the catching handler in
+    /// place of the error message
+    thrown: Option<Value>,
+    /// creation time, the epoch for the monotonic `clock()` native
+    #[cfg(feature = "std")]
+    pub(crate) start: Instant,
+    /// xorshift state for the `random()` native; reseedable via `seed_rng`
+    pub(crate) rng_state: u64,
+    /// when set, `step` tallies each executed opcode into `opcode_counts`
+    pub profile: bool,
+    opcode_counts: [u64; N_OPCODES],
+    /// when set, `step` records each executed source line into
+    /// `covered_lines`
+    pub coverage: bool,
+    covered_lines: BTreeSet<u32>,
+    pub(crate) out: OutputSink,
+}
+
+/// Destination for `print` output. With `std` this is any `io::Write`
+/// (stdout by default); without it, hosts supply a `core::fmt::Write`
+/// implementation via [`VM::set_output`].
+#[cfg(feature = "std")]
+pub type OutputSink = Box<dyn std::io::Write>;
+#[cfg(not(feature = "std"))]
+pub type OutputSink = Box<dyn core::fmt::Write>;
+
+/// Default sink for no_std builds: discards everything written to it.
+#[cfg(not(feature = "std"))]
+struct NullSink;
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Write for NullSink {
+    fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> Self {
+        Self::with_config(VMConfig::default())
+    }
+
+    pub fn with_config(config: VMConfig) -> Self {
+        let mut vm = Self {
+            config,
+            stack: Stack::new(),
+            frames: Vec::with_capacity(config.max_frames.min(MAX_FRAMES)),
+            globals: Table::new(),
+            global_cache: Vec::new(),
+            property_cache: Vec::new(),
+            strings: Table::new(),
+            heap_objects: Vec::new(),
+            gc_stats: GCStats {
+                bytes_allocated: 0,
+                next_gc: config.initial_gc_threshold,
+            },
+            collecting: false,
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            thrown: None,
+            #[cfg(feature = "std")]
+            start: Instant::now(),
+            rng_state: 0x9e37_79b9_7f4a_7c15,
+            profile: false,
+            opcode_counts: [0; N_OPCODES],
+            coverage: false,
+            covered_lines: BTreeSet::new(),
+            #[cfg(feature = "std")]
+            out: Box::new(std::io::stdout()),
+            #[cfg(not(feature = "std"))]
+            out: Box::new(NullSink),
+        };
+        vm.init_natives();
+        vm
+    }
+
+    /// Redirects `print` output, primarily for tests.
+    pub fn set_output(&mut self, out: OutputSink) {
+        self.out = out;
+    }
+
+    /// Disassembles the named function, looking among globals and live heap
+    /// objects. Handy for inspecting bytecode from the REPL.
+    pub fn disassemble_function(&self, name: &str) -> Option<String> {
+        let function = self
+            .globals
+            .iter()
+            .map(|(_, value)| value)
+            .chain(self.heap_objects.iter())
+            .find_map(|value| match value {
+                Value::Function(f) if f.name_str() == name => Some(Rc::clone(f)),
+                Value::Closure(c) if c.function.name_str() == name => Some(Rc::clone(&c.function)),
+                _ => None,
+            })?;
+        Some(function.chunk.disassemble(name))
+    }
+
+    /// Parameter count of the named global callable, or `None` when it is
+    /// undefined, not callable, or a native (whose arity is unknown).
+    pub fn global_arity(&self, name: &str) -> Option<u8> {
+        match self.globals.get(name)? {
+            Value::Function(f) => Some(f.arg_count),
+            Value::Closure(c) => Some(c.function.arg_count),
+            Value::BoundMethod(b) => Some(b.method.function.arg_count),
+            _ => None,
+        }
+    }
+
+    /// Compiles `source` and returns the disassembly of the script plus
+    /// every nested function, without executing anything. Backs the `dump`
+    /// CLI subcommand.
+    pub fn dump(&mut self, source: &str) -> Result<String, InterpretError> {
+        fn walk(function: &Function, out: &mut String) {
+            out.push_str(&function.chunk.disassemble(function.name_str()));
+            for constant in &function.chunk.constants {
+                if let Value::Function(nested) = constant {
+                    walk(nested, out);
+                }
+            }
+        }
+        let script = self.compile(source)?;
+        let mut out = String::new();
+        walk(&script, &mut out);
+        Ok(out)
+    }
+
+    /// Doc comment of the named function, if it was declared with one.
+    pub fn doc_for(&self, name: &str) -> Option<String> {
+        self.globals
+            .iter()
+            .map(|(_, value)| value)
+            .chain(self.heap_objects.iter())
+            .find_map(|value| match value {
+                Value::Function(f) if f.name_str() == name => f.doc.clone(),
+                Value::Closure(c) if c.function.name_str() == name => c.function.doc.clone(),
+                _ => None,
+            })
+            .map(|doc| doc.to_string())
+    }
+
+    /// Builder-style override of the GC schedule: the first collection
+    /// triggers at `initial_threshold` bytes, and each collection sets the
+    /// next trigger to live bytes times `grow_factor`.
+    pub fn with_gc(mut self, initial_threshold: usize, grow_factor: usize) -> Self {
+        self.config.initial_gc_threshold = initial_threshold;
+        self.config.gc_grow_factor = grow_factor;
+        self.gc_stats.next_gc = initial_threshold;
+        self
+    }
+
+    /// Builder-style toggle for [`VMConfig::string_coercion`].
+    pub fn with_string_coercion(mut self, enabled: bool) -> Self {
+        self.config.string_coercion = enabled;
+        self
+    }
+
+    /// Builder-style toggle for [`VMConfig::total_ordering`].
+    pub fn with_total_ordering(mut self, enabled: bool) -> Self {
+        self.config.total_ordering = enabled;
+        self
+    }
+
+    /// Comparison under the opt-in cross-type total order; types with no
+    /// defined order still error.
+    fn total_order(&mut self, a: &Value, b: &Value) -> Result<core::cmp::Ordering, InterpretError> {
+        a.total_cmp(b)
+            .ok_or_else(|| self.err("Operands must be orderable values."))
+    }
+
+    /// Per-opcode execution tallies gathered while [`profile`](Self::profile)
+    /// is set, indexed in `OpCode::VARIANTS` order.
+    pub fn opcode_counts(&self) -> &[u64] {
+        &self.opcode_counts
+    }
+
+    /// Number of live call frames. Zero when nothing is executing; useful
+    /// with [`interpret_bounded`](Self::interpret_bounded) for host-side
+    /// recursion guards.
+    pub fn frame_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of live value-stack slots.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.cursor
+    }
+
+    /// Value of the named local in the current (innermost) frame, resolved
+    /// through the chunk's retained debug records. Only locals whose scope
+    /// covers the current instruction are considered; when shadowing leaves
+    /// several live, the innermost (latest-opened) scope wins. Returns
+    /// `None` when nothing is executing or the name is not live.
+    #[cfg(feature = "debug_info")]
+    pub fn local(&self, name: &str) -> Option<Value> {
+        let frame = self.frames.last()?;
+        let info = frame
+            .closure
+            .function
+            .chunk
+            .locals_at(frame.ip)
+            .filter(|l| l.name == name)
+            .max_by_key(|l| l.scope_start_offset)?;
+        let slot = frame.sp + info.slot as usize;
+        (slot < self.stack.cursor).then(|| self.stack.get(slot).clone())
+    }
+
+    /// Source lines executed while [`coverage`](Self::coverage) is set.
+    pub fn covered_lines(&self) -> &BTreeSet<u32> {
+        &self.covered_lines
+    }
+
+    /// Owned copy of every defined global (natives included), for
+    /// inspection and test assertions beyond stdout.
+    pub fn globals_snapshot(&self) -> Vec<(String, Value)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    pub fn gc_stats(&self) -> GCStats {
+        self.gc_stats
+    }
+
+    /// Compiles and runs `source`. Globals and heap state persist — including
+    /// across errors, which only discard the in-flight execution — so
+    /// embedders and the REPL can keep calling into earlier definitions.
+    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretError> {
+        let function = match self.compile(source) {
+            Ok(function) => function,
+            Err(e) => {
+                self.reset_preserving_globals();
+                return Err(e);
+            }
+        };
+        self.execute(function)
+    }
+
+    /// Compiles `source` and runs at most `max_steps` instructions,
+    /// returning `VMState::Yielded` when the budget runs out mid-script.
+    /// Continue a yielded execution with [`resume`](Self::resume); a host can
+    /// interleave several VMs this way for cooperative scheduling.
+    pub fn interpret_bounded(
+        &mut self,
+        source: &str,
+        max_steps: u64,
+    ) -> Result<VMState, InterpretError> {
+        let function = match self.compile(source) {
+            Ok(function) => function,
+            Err(e) => {
+                self.reset_preserving_globals();
+                return Err(e);
+            }
+        };
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: Vec::new(),
+        });
+        self.stack
+            .push(Value::Closure(Rc::clone(&closure)))
+            .expect("empty stack can hold the script");
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            sp: 0,
+        });
+        self.resume(max_steps)
+    }
+
+    /// Runs up to `max_steps` more instructions of an execution started by
+    /// [`interpret_bounded`](Self::interpret_bounded).
+    pub fn resume(&mut self, max_steps: u64) -> Result<VMState, InterpretError> {
+        if self.frames.is_empty() {
+            return Ok(VMState::Done);
+        }
+        let result = self.run_bounded(0, max_steps);
+        match result {
+            Ok(Some(_)) => Ok(VMState::Done),
+            Ok(None) => Ok(VMState::Yielded),
+            Err(error) => {
+                self.print_stack_trace();
+                self.reset_preserving_globals();
+                Err(error)
+            }
+        }
+    }
+
+    /// Runs an already-compiled script function to completion.
+    pub(crate) fn execute(&mut self, function: Function) -> Result<(), InterpretError> {
+        let closure = Rc::new(Closure {
+            function: Rc::new(function),
+            upvalues: Vec::new(),
+        });
+        self.stack
+            .push(Value::Closure(Rc::clone(&closure)))
+            .expect("empty stack can hold the script");
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            sp: 0,
+        });
+        let result = self.run(0);
+        if result.is_err() {
+            self.print_stack_trace();
+            self.reset_preserving_globals();
+        }
+        result.map(|_| ())
+    }
+
+    /// Calls a global function by name with Rust-provided arguments,
+    /// returning its result. Runs as a sub-execution: the VM's globals and
+    /// heap survive, so a script can be interpreted first to define the
+    /// function.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, InterpretError> {
+        if args.len() > u8::MAX as usize {
+            return Err(InterpretError::RuntimeError {
+                msg: "Cannot pass more than 255 arguments.".to_string(),
+                kind: ErrorKind::Arity,
+                line: None,
+                trace: None,
+            });
+        }
+        let name = self.intern(name);
+        let Some(callee) = self.globals.get(&name).cloned() else {
+            return Err(InterpretError::RuntimeError {
+                msg: format!("Undefined variable '{name}'."),
+                kind: ErrorKind::UndefinedVariable,
+                line: None,
+                trace: None,
+            });
+        };
+        let base = self.frames.len();
+        let entry = self.stack.cursor;
+        let result = (|| {
+            self.push(callee.clone())?;
+            for arg in args {
+                self.push(arg.clone())?;
+            }
+            self.call_value(callee, args.len() as u8)?;
+            if self.frames.len() == base {
+                // natives and no-init constructors complete inside
+                // `call_value`, leaving their result on the stack
+                return Ok(self.stack.pop());
+            }
+            self.run(base)
+        })();
+        if result.is_err() {
+            self.print_stack_trace();
+            self.close_upvalues(entry);
+            self.frames.truncate(base);
+            self.stack.truncate(entry);
+            while matches!(self.handlers.last(), Some(h) if h.frame_depth > base) {
+                self.handlers.pop();
+            }
+            self.thrown = None;
+        }
+        result
+    }
+
+    pub fn compile(&mut self, source: &str) -> Result<Function, InterpretError> {
+        compiler::compile(
+            source,
+            &mut self.strings,
+            &mut self.heap_objects,
+            &mut self.gc_stats,
+        )
+    }
+
+    /// Clears the in-flight execution state (stack, frames, upvalues,
+    /// handlers) while keeping globals, interned strings, and their heap
+    /// objects alive. Anything orphaned by the unwind stays in
+    /// `heap_objects` with its allocation still counted, so the next GC
+    /// cycle sweeps it without skewing the accounting.
+    pub fn reset_preserving_globals(&mut self) {
+        self.stack.truncate(0);
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.thrown = None;
+    }
+
+    /// Tears the VM down to its initial state, re-registering natives.
+    pub fn reset(&mut self) {
+        self.stack.truncate(0);
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.thrown = None;
+        self.globals = Table::new();
+        self.strings = Table::new();
+        self.heap_objects.clear();
+        self.gc_stats = GCStats {
+            bytes_allocated: 0,
+            next_gc: self.config.initial_gc_threshold,
+        };
+        self.init_natives();
+    }
+
+    fn init_natives(&mut self) {
+        #[cfg(feature = "std")]
+        {
+            self.define_native("clock", natives::clock);
+            self.define_native("time", natives::time);
+            self.define_native("sleep", natives::sleep);
+        }
+        self.define_native("typeof", natives::type_of);
+        self.define_native("assert", natives::assert);
+        self.define_native("format", natives::format);
+        self.define_native("num", natives::num);
+        self.define_native("min", natives::min);
+        self.define_native("max", natives::max);
+        self.define_native("clamp", natives::clamp);
+        self.define_native("lerp", natives::lerp);
+        self.define_native("sign", natives::sign);
+        self.define_native("seed_rng", natives::seed_rng);
+        self.define_native("random", natives::random);
+        self.define_native("len", natives::len);
+        self.define_native("arity", natives::arity);
+        self.define_native("fields", natives::fields);
+        self.define_native("has", natives::has);
+        self.define_native("is", natives::is);
+        self.define_native("superclass", natives::superclass);
+        self.define_native("debug", natives::debug);
+        self.define_native("print", natives::print);
+        self.define_native("freeze", natives::freeze);
+        self.define_native("weakref", natives::weakref);
+        self.define_native("deref", natives::deref);
+        self.define_native("get", natives::get);
+        self.define_native("set", natives::set);
+        self.define_native("split", natives::split);
+        self.define_native("chars", natives::chars);
+        self.define_native("substr", natives::substr);
+        self.define_native("contains", natives::contains);
+        self.define_native("index_of", natives::index_of);
+        self.define_native("ord", natives::ord);
+        self.define_native("chr", natives::chr);
+    }
+
+    fn define_native(&mut self, name: &'static str, func: crate::value::NativeFunc) {
+        let key = self.intern(name);
+        self.globals
+            .set(key, Value::NativeFn(crate::value::Native { name, func }));
+    }
+
+    /// Interns `s` in the VM's string table, tracking any new allocation.
+    pub(crate) fn intern(&mut self, s: &str) -> LoxStr {
+        alloc_str(
+            s,
+            &mut self.strings,
+            &mut self.heap_objects,
+            &mut self.gc_stats,
+        )
+    }
+
+    /// Builds a `Value::String` for embedders, interned in the VM's string
+    /// table and tracked by the GC. Constructing `Value::String` directly
+    /// from an `Rc<str>` bypasses both, so equality degrades to a content
+    /// compare and the allocation never shows up in the GC accounting —
+    /// always go through here when handing strings to `call_function`.
+    pub fn intern_str(&mut self, s: &str) -> Value {
+        Value::String(self.intern(s))
+    }
+
+    /// Runs until the frame stack drops back to `base` frames, returning the
+    /// value produced by the frame at that depth. Runtime errors unwind to
+    /// the nearest handler installed within this execution; without one they
+    /// propagate to the caller.
+    fn run(&mut self, base: usize) -> Result<Value, InterpretError> {
+        match self.run_bounded(base, u64::MAX) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => unreachable!("unbounded run cannot exhaust its budget"),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Like [`run`](Self::run), but executes at most `budget` instructions.
+    /// `Ok(None)` means the budget ran out with the execution still in
+    /// flight; the frame and stack state stay intact for [`resume`](Self::resume).
+    fn run_bounded(&mut self, base: usize, mut budget: u64) -> Result<Option<Value>, InterpretError> {
+        loop {
+            if budget == 0 {
+                return Ok(None);
+            }
+            budget -= 1;
+            match self.step(base) {
+                Ok(Some(value)) => return Ok(Some(value)),
+                Ok(None) => {}
+                Err(error) => self.unwind(error, base)?,
+            }
+        }
+    }
+
+    /// Transfers control to the innermost handler belonging to this
+    /// execution, binding the error on the stack. Compile errors and errors
+    /// with no handler are passed back up.
+    fn unwind(&mut self, error: InterpretError, base: usize) -> Result<(), InterpretError> {
+        let InterpretError::RuntimeError { msg, .. } = &error else {
+            return Err(error);
+        };
+        match self.handlers.last() {
+            Some(handler) if handler.frame_depth > base => {
+                let msg = msg.clone();
+                let handler = self.handlers.pop().unwrap();
+                self.close_upvalues(handler.stack_cursor);
+                self.frames.truncate(handler.frame_depth);
+                self.stack.truncate(handler.stack_cursor);
+                let value = match self.thrown.take() {
+                    Some(value) => value,
+                    None => Value::String(self.intern(&msg)),
+                };
+                self.push(value)?;
+                self.frame_mut().ip = handler.catch_ip;
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // decode helpers
+    // ------------------------------------------------------------------
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no active call frame")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.frame().closure.function.chunk
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.data[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let lo = self.read_byte();
+        let hi = self.read_byte();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        let bytes = [
+            self.read_byte(),
+            self.read_byte(),
+            self.read_byte(),
+            self.read_byte(),
+        ];
+        u32::from_le_bytes(bytes)
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let idx = self.read_byte() as usize;
+        self.chunk().constants[idx].clone()
+    }
+
+    fn read_string_constant(&mut self) -> LoxStr {
+        match self.read_constant() {
+            Value::String(s) => s,
+            other => unreachable!("expected string constant, got {other:?}"),
+        }
+    }
+
+    fn string_constant(&self, idx: usize) -> LoxStr {
+        match &self.chunk().constants[idx] {
+            Value::String(s) => Rc::clone(s),
+            other => unreachable!("expected string constant, got {other:?}"),
+        }
+    }
+
+    /// Records where `name` currently lives in `globals` so the next
+    /// `ReadGlobal`/`WriteGlobal` with the same operand skips the probe.
+    fn cache_global(&mut self, idx: usize, name: &LoxStr) {
+        if idx >= self.global_cache.len() {
+            self.global_cache.resize(idx + 1, None);
+        }
+        self.global_cache[idx] = self.globals.slot_of(name);
+    }
+
+    /// Property-access counterpart of [`cache_global`](Self::cache_global),
+    /// recording the fields-table slot `name` resolved to.
+    fn cache_property(&mut self, idx: usize, name: &LoxStr, fields: &Table) {
+        if idx >= self.property_cache.len() {
+            self.property_cache.resize(idx + 1, None);
+        }
+        self.property_cache[idx] = fields.slot_of(name);
+    }
+
+    fn current_line(&self) -> u32 {
+        let frame = self.frame();
+        frame
+            .closure
+            .function
+            .chunk
+            .line_for_offset(frame.ip.saturating_sub(1))
+    }
+
+    fn err(&self, msg: impl Into<String>) -> InterpretError {
+        let msg = msg.into();
+        let line = (!self.frames.is_empty()).then(|| self.current_line());
+        tracing::error!("[line {}] {msg}", line.unwrap_or(0));
+        // quote the offending source line when we know it
+        if let Some(n) = line {
+            let text = self.frame().closure.function.chunk.source_line(n);
+            if !text.trim().is_empty() {
+                tracing::error!("  {n} | {}", text.trim_end());
+            }
+        }
+        let trace = (!self.frames.is_empty()).then(|| self.stack_trace_string());
+        InterpretError::RuntimeError {
+            kind: ErrorKind::classify(&msg),
+            line,
+            msg,
+            trace,
+        }
+    }
+
+    /// Renders the live call stack as `[line N] in <name>` lines, innermost
+    /// frame first.
+    pub fn stack_trace_string(&self) -> String {
+        let mut trace = String::new();
+        for frame in self.frames.iter().rev() {
+            let function = &frame.closure.function;
+            let line = function.chunk.line_for_offset(frame.ip.saturating_sub(1));
+            if !trace.is_empty() {
+                trace.push('\n');
+            }
+            trace.push_str(&format!("[line {line}] in {}", function.name_str()));
+        }
+        trace
+    }
+
+    fn print_stack_trace(&self) {
+        for line in self.stack_trace_string().lines() {
+            tracing::error!("{line}");
+        }
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), InterpretError> {
+        self.stack.push(value).map_err(|msg| self.err(msg))
+    }
+
+    // ------------------------------------------------------------------
+    // execution
+    // ------------------------------------------------------------------
+
+    /// Executes a single instruction. Returns `Some(value)` when the frame at
+    /// depth `base` returns.
+    fn step(&mut self, base: usize) -> Result<Option<Value>, InterpretError> {
+        if tracing::enabled!(tracing::Level::TRACE) {
+            let frame = self.frame();
+            let (text, _) = frame.closure.function.chunk.disassemble_instr(frame.ip);
+            tracing::trace!("{text}");
+        }
+        if self.coverage {
+            let frame = self.frame();
+            let line = frame.closure.function.chunk.line_for_offset(frame.ip);
+            self.covered_lines.insert(line);
+        }
+        let op = OpCode::from_repr(self.read_byte()).expect("invalid opcode");
+        if self.profile {
+            self.opcode_counts[op as usize] += 1;
+        }
+        match op {
+            OpCode::Constant => {
+                let value = self.read_constant();
+                self.push(value)?;
+            }
+            OpCode::Nil => self.push(Value::Nil)?,
+            OpCode::True => self.push(Value::Bool(true))?,
+            OpCode::False => self.push(Value::Bool(false))?,
+            OpCode::Zero => self.push(Value::Float(0.0))?,
+            OpCode::One => self.push(Value::Float(1.0))?,
+            OpCode::Pop => {
+                self.stack.pop();
+            }
+            OpCode::StackSub => {
+                let n = self.read_byte() as usize;
+                let to = self.stack.cursor - n;
+                self.stack.truncate(to);
+            }
+            OpCode::Dup => {
+                let value = self.stack.top().clone();
+                self.push(value)?;
+            }
+            OpCode::ReadLocal => {
+                let slot = self.read_byte() as usize;
+                let value = self.stack.get(self.frame().sp + slot).clone();
+                self.push(value)?;
+            }
+            OpCode::WriteLocal => {
+                let slot = self.read_byte() as usize;
+                let value = self.stack.top().clone();
+                let sp = self.frame().sp;
+                self.stack.set(sp + slot, value);
+            }
+            OpCode::DefineGlobal => {
+                let name = self.read_string_constant();
+                let value = self.stack.pop();
+                self.globals.set(name, value);
+            }
+            OpCode::ReadGlobal => {
+                let idx = self.read_byte() as usize;
+                let name = self.string_constant(idx);
+                if let Some(Some(slot)) = self.global_cache.get(idx) {
+                    if let Some(value) = self.globals.get_at(*slot, &name) {
+                        let value = value.clone();
+                        self.push(value)?;
+                        return Ok(None);
+                    }
+                }
+                match self.globals.get(&name) {
+                    Some(value) => {
+                        let value = value.clone();
+                        self.cache_global(idx, &name);
+                        self.push(value)?;
+                    }
+                    None => return Err(self.err(format!("Undefined variable '{name}'."))),
+                }
+            }
+            OpCode::WriteGlobal => {
+                let idx = self.read_byte() as usize;
+                let name = self.string_constant(idx);
+                let value = self.stack.top().clone();
+                if let Some(Some(slot)) = self.global_cache.get(idx) {
+                    if self.globals.set_at(*slot, &name, value.clone()) {
+                        return Ok(None);
+                    }
+                }
+                if self.globals.set(Rc::clone(&name), value) {
+                    self.globals.delete(&name);
+                    return Err(self.err(format!("Undefined variable '{name}'.")));
+                }
+                self.cache_global(idx, &name);
+            }
+            OpCode::ReadUpval => {
+                let idx = self.read_byte() as usize;
+                let cell = Rc::clone(&self.frame().closure.upvalues[idx]);
+                let value = match &*cell.borrow() {
+                    Upvalue::Open(slot) => self.stack.get(*slot).clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                self.push(value)?;
+            }
+            OpCode::WriteUpval => {
+                let idx = self.read_byte() as usize;
+                let cell = Rc::clone(&self.frame().closure.upvalues[idx]);
+                let value = self.stack.top().clone();
+                let mut upval = cell.borrow_mut();
+                match &mut *upval {
+                    Upvalue::Open(slot) => {
+                        let slot = *slot;
+                        drop(upval);
+                        self.stack.set(slot, value);
+                    }
+                    Upvalue::Closed(closed) => *closed = value,
+                }
+            }
+            OpCode::ReadProperty => {
+                let idx = self.read_byte() as usize;
+                let name = self.string_constant(idx);
+                let receiver = self.stack.pop();
+                if let Value::Class(class) = &receiver {
+                    let method = class.statics.borrow().get(&name).cloned();
+                    let Some(method) = method else {
+                        return Err(self.err(format!("Undefined property '{name}'.")));
+                    };
+                    self.push(method)?;
+                    return Ok(None);
+                }
+                let Value::Instance(instance) = &receiver else {
+                    return Err(self.err("Cannot read property of non-instance."));
+                };
+                if let Some(Some(slot)) = self.property_cache.get(idx) {
+                    if let Some(value) = instance.fields.borrow().get_at(*slot, &name) {
+                        let value = value.clone();
+                        self.push(value)?;
+                        return Ok(None);
+                    }
+                }
+                let field = instance.fields.borrow().get(&name).cloned();
+                if let Some(value) = field {
+                    self.cache_property(idx, &name, &instance.fields.borrow());
+                    self.push(value)?;
+                } else {
+                    let method = instance.class.find_method(&name);
+                    let Some(Value::Closure(method)) = method else {
+                        return Err(self.err(format!("Undefined property '{name}'.")));
+                    };
+                    if method.function.is_getter {
+                        let base = self.frames.len();
+                        self.push(receiver.clone())?;
+                        self.call_closure(method, 0)?;
+                        let result = self.run(base)?;
+                        self.push(result)?;
+                    } else {
+                        let bound = Value::BoundMethod(Rc::new(BoundMethod {
+                            receiver: receiver.clone(),
+                            method,
+                        }));
+                        self.register(bound.clone());
+                        self.push(bound)?;
+                    }
+                }
+            }
+            OpCode::WriteProperty => {
+                let idx = self.read_byte() as usize;
+                let name = self.string_constant(idx);
+                let value = self.stack.pop();
+                let receiver = self.stack.pop();
+                let Value::Instance(instance) = &receiver else {
+                    return Err(self.err("Cannot write property of non-instance."));
+                };
+                if instance.frozen.get() {
+                    return Err(self.err("Cannot write property of frozen instance."));
+                }
+                if let Some(Some(slot)) = self.property_cache.get(idx) {
+                    if instance.fields.borrow_mut().set_at(*slot, &name, value.clone()) {
+                        self.push(value)?;
+                        return Ok(None);
+                    }
+                }
+                instance.fields.borrow_mut().set(Rc::clone(&name), value.clone());
+                self.cache_property(idx, &name, &instance.fields.borrow());
+                self.push(value)?;
+            }
+            OpCode::Equal => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("eq", &a, &b)? {
+                    self.push(Value::Bool(a == b))?;
+                }
+            }
+            OpCode::Greater => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("gt", &a, &b)? {
+                    let result = if self.config.total_ordering {
+                        Value::Bool(self.total_order(&a, &b)?.is_gt())
+                    } else {
+                        a.greater(b).map_err(|msg| self.err(msg))?
+                    };
+                    self.push(result)?;
+                }
+            }
+            OpCode::Less => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("lt", &a, &b)? {
+                    let result = if self.config.total_ordering {
+                        Value::Bool(self.total_order(&a, &b)?.is_lt())
+                    } else {
+                        a.less(b).map_err(|msg| self.err(msg))?
+                    };
+                    self.push(result)?;
+                }
+            }
+            OpCode::Add => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("add", &a, &b)? {
+                    let (a, b) = if self.config.string_coercion {
+                        self.coerce_add_operands(a, b)
+                    } else {
+                        (a, b)
+                    };
+                    let result = a
+                        .add(
+                            b,
+                            &mut self.strings,
+                            &mut self.heap_objects,
+                            &mut self.gc_stats,
+                        )
+                        .map_err(|msg| self.err(msg))?;
+                    self.push(result)?;
+                    self.maybe_collect();
+                }
+            }
+            OpCode::Sub => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("sub", &a, &b)? {
+                    let result = a.sub(b).map_err(|msg| self.err(msg))?;
+                    self.push(result)?;
+                }
+            }
+            OpCode::Mul => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("mul", &a, &b)? {
+                    let result = a
+                        .mul(
+                            b,
+                            &mut self.strings,
+                            &mut self.heap_objects,
+                            &mut self.gc_stats,
+                        )
+                        .map_err(|msg| self.err(msg))?;
+                    self.push(result)?;
+                    self.maybe_collect();
+                }
+            }
+            OpCode::Div => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                if !self.try_binary_overload("div", &a, &b)? {
+                    let result = a.div(b).map_err(|msg| self.err(msg))?;
+                    self.push(result)?;
+                }
+            }
+            OpCode::BitAnd => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                let result = a.bit_and(b).map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::BitOr => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                let result = a.bit_or(b).map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::BitXor => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                let result = a.bit_xor(b).map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::Shl => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                let result = a.shl(b).map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::Shr => {
+                let b = self.stack.pop();
+                let a = self.stack.pop();
+                let result = a.shr(b).map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::BitNot => {
+                let a = self.stack.pop();
+                let result = a.bit_not().map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::Not => {
+                let value = self.stack.pop();
+                self.push(Value::Bool(!value.is_truthy()))?;
+            }
+            OpCode::Negate => {
+                let value = self.stack.pop();
+                let result = value.negate().map_err(|msg| self.err(msg))?;
+                self.push(result)?;
+            }
+            OpCode::Print => {
+                let value = self.stack.pop();
+                let _ = writeln!(self.out, "{value}");
+            }
+            OpCode::Jump => {
+                let offset = self.read_u16() as usize;
+                self.frame_mut().ip += offset;
+            }
+            OpCode::JumpFalsey => {
+                let offset = self.read_u16() as usize;
+                if !self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpTruthy => {
+                let offset = self.read_u16() as usize;
+                if self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpFalseyOrPop => {
+                let offset = self.read_u16() as usize;
+                if !self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                } else {
+                    self.stack.pop();
+                }
+            }
+            OpCode::JumpFalseyPop => {
+                let offset = self.read_u16() as usize;
+                if !self.stack.pop().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpTruthyPop => {
+                let offset = self.read_u16() as usize;
+                if self.stack.pop().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpTruthyOrPop => {
+                let offset = self.read_u16() as usize;
+                if self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                } else {
+                    self.stack.pop();
+                }
+            }
+            OpCode::JumpBack => {
+                let offset = self.read_u16() as usize;
+                self.frame_mut().ip -= offset;
+            }
+            OpCode::JumpLong => {
+                let offset = self.read_u32() as usize;
+                self.frame_mut().ip += offset;
+            }
+            OpCode::JumpFalseyLong => {
+                let offset = self.read_u32() as usize;
+                if !self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpTruthyLong => {
+                let offset = self.read_u32() as usize;
+                if self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpFalseyPopLong => {
+                let offset = self.read_u32() as usize;
+                if !self.stack.pop().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpTruthyPopLong => {
+                let offset = self.read_u32() as usize;
+                if self.stack.pop().is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            }
+            OpCode::JumpFalseyOrPopLong => {
+                let offset = self.read_u32() as usize;
+                if !self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                } else {
+                    self.stack.pop();
+                }
+            }
+            OpCode::JumpTruthyOrPopLong => {
+                let offset = self.read_u32() as usize;
+                if self.stack.top().is_truthy() {
+                    self.frame_mut().ip += offset;
+                } else {
+                    self.stack.pop();
+                }
+            }
+            OpCode::JumpBackLong => {
+                let offset = self.read_u32() as usize;
+                self.frame_mut().ip -= offset;
+            }
+            OpCode::Call => {
+                let arg_count = self.read_byte();
+                let callee = self.stack.peek(arg_count as usize).clone();
+                self.call_value(callee, arg_count)?;
+            }
+            OpCode::Closure => {
+                let Value::Function(function) = self.read_constant() else {
+                    unreachable!("Closure operand must be a function constant");
+                };
+                let mut upvalues = Vec::with_capacity(function.upval_count);
+                for _ in 0..function.upval_count {
+                    let is_local = self.read_byte() == 1;
+                    let index = self.read_byte() as usize;
+                    if is_local {
+                        let slot = self.frame().sp + index;
+                        upvalues.push(self.capture_upval(slot));
+                    } else {
+                        upvalues.push(Rc::clone(&self.frame().closure.upvalues[index]));
+                    }
+                }
+                let closure = Value::Closure(Rc::new(Closure { function, upvalues }));
+                self.register(closure.clone());
+                self.push(closure)?;
+            }
+            OpCode::CloseUpval => {
+                self.close_upvalues(self.stack.cursor - 1);
+                self.stack.pop();
+            }
+            OpCode::Return => {
+                let result = self.stack.pop();
+                let frame = self.frames.pop().expect("returning without a frame");
+                self.close_upvalues(frame.sp);
+                self.stack.truncate(frame.sp);
+                // a return inside a try block leaves its handler installed
+                while matches!(self.handlers.last(), Some(h) if h.frame_depth > self.frames.len())
+                {
+                    self.handlers.pop();
+                }
+                if self.frames.len() == base {
+                    return Ok(Some(result));
+                }
+                self.push(result)?;
+            }
+            OpCode::PushHandler => {
+                let offset = self.read_u16() as usize;
+                let catch_ip = self.frame().ip + offset;
+                self.handlers.push(Handler {
+                    frame_depth: self.frames.len(),
+                    stack_cursor: self.stack.cursor,
+                    catch_ip,
+                });
+            }
+            OpCode::PushHandlerLong => {
+                let offset = self.read_u32() as usize;
+                let catch_ip = self.frame().ip + offset;
+                self.handlers.push(Handler {
+                    frame_depth: self.frames.len(),
+                    stack_cursor: self.stack.cursor,
+                    catch_ip,
+                });
+            }
+            OpCode::PopHandler => {
+                self.handlers.pop();
+            }
+            OpCode::Throw => {
+                let value = self.stack.pop();
+                let error = self.err(format!("Uncaught error: {value}"));
+                self.thrown = Some(value);
+                return Err(error);
+            }
+            OpCode::Class => {
+                let name = self.read_string_constant();
+                let class = Value::Class(Rc::new(crate::value::Class::new(name)));
+                self.register(class.clone());
+                self.push(class)?;
+            }
+            OpCode::Method => {
+                let name = self.read_string_constant();
+                let method = self.stack.pop();
+                let Value::Class(class) = self.stack.top() else {
+                    unreachable!("Method opcode without class on stack");
+                };
+                class.methods.borrow_mut().set(name, method);
+            }
+            OpCode::StaticMethod => {
+                let name = self.read_string_constant();
+                let method = self.stack.pop();
+                let Value::Class(class) = self.stack.top() else {
+                    unreachable!("StaticMethod opcode without class on stack");
+                };
+                class.statics.borrow_mut().set(name, method);
+            }
+            OpCode::Inherit => {
+                let Value::Class(superclass) = self.stack.peek(1).clone() else {
+                    return Err(self.err("Superclass must be a class."));
+                };
+                let Value::Class(subclass) = self.stack.top() else {
+                    unreachable!("Inherit opcode without class on stack");
+                };
+                *subclass.superclass.borrow_mut() = Some(Rc::clone(&superclass));
+                self.stack.pop();
+            }
+            OpCode::List => {
+                let count = self.read_byte() as usize;
+                let mut items = vec![Value::Nil; count];
+                for slot in items.iter_mut().rev() {
+                    *slot = self.stack.pop();
+                }
+                let list = Value::List(Rc::new(RefCell::new(items)));
+                self.register(list.clone());
+                self.push(list)?;
+            }
+            OpCode::IndexGet => {
+                let idx = self.stack.pop();
+                let receiver = self.stack.pop();
+                let Value::List(list) = &receiver else {
+                    return Err(self.err("Can only index into lists."));
+                };
+                let i = self.check_index(&idx, list.borrow().len())?;
+                let value = list.borrow()[i].clone();
+                self.push(value)?;
+            }
+            OpCode::IndexSet => {
+                let value = self.stack.pop();
+                let idx = self.stack.pop();
+                let receiver = self.stack.pop();
+                let Value::List(list) = &receiver else {
+                    return Err(self.err("Can only index into lists."));
+                };
+                let i = self.check_index(&idx, list.borrow().len())?;
+                list.borrow_mut()[i] = value.clone();
+                self.push(value)?;
+            }
+            OpCode::ListLen => {
+                let receiver = self.stack.pop();
+                let Value::List(list) = &receiver else {
+                    return Err(self.err("Can only take the length of a list."));
+                };
+                let len = list.borrow().len() as f64;
+                self.push(Value::Float(len))?;
+            }
+            OpCode::Invoke => {
+                let name = self.read_string_constant();
+                let arg_count = self.read_byte();
+                let receiver = self.stack.peek(arg_count as usize).clone();
+                if let Value::Class(class) = &receiver {
+                    let method = class.statics.borrow().get(&name).cloned();
+                    let Some(method) = method else {
+                        return Err(self.err(format!("Undefined property '{name}'.")));
+                    };
+                    let slot = self.stack.cursor - arg_count as usize - 1;
+                    self.stack.set(slot, method.clone());
+                    self.call_value(method, arg_count)?;
+                    return Ok(None);
+                }
+                let Value::Instance(instance) = &receiver else {
+                    return Err(self.err("Cannot read property of non-instance."));
+                };
+                // a field shadowing the method name is called as a value
+                let field = instance.fields.borrow().get(&name).cloned();
+                if let Some(field) = field {
+                    let slot = self.stack.cursor - arg_count as usize - 1;
+                    self.stack.set(slot, field.clone());
+                    self.call_value(field, arg_count)?;
+                } else {
+                    let method = instance.class.find_method(&name);
+                    let Some(Value::Closure(method)) = method else {
+                        return Err(self.err(format!("Undefined property '{name}'.")));
+                    };
+                    if method.function.is_getter {
+                        // the getter produces the callee; call its result
+                        let base = self.frames.len();
+                        self.push(receiver.clone())?;
+                        self.call_closure(method, 0)?;
+                        let callee = self.run(base)?;
+                        let slot = self.stack.cursor - arg_count as usize - 1;
+                        self.stack.set(slot, callee.clone());
+                        self.call_value(callee, arg_count)?;
+                    } else {
+                        self.call_closure(method, arg_count)?;
+                    }
+                }
+            }
+            OpCode::SuperInvoke => {
+                let name = self.read_string_constant();
+                let arg_count = self.read_byte();
+                let Value::Class(superclass) = self.stack.pop() else {
+                    unreachable!("SuperInvoke opcode without superclass on stack");
+                };
+                let method = superclass.find_method(&name);
+                let Some(Value::Closure(method)) = method else {
+                    return Err(self.err(format!("Undefined property '{name}'.")));
+                };
+                self.call_closure(method, arg_count)?;
+            }
+            OpCode::Super => {
+                let name = self.read_string_constant();
+                let Value::Class(superclass) = self.stack.pop() else {
+                    unreachable!("Super opcode without superclass on stack");
+                };
+                let receiver = self.stack.pop();
+                let method = superclass.find_method(&name);
+                let Some(Value::Closure(method)) = method else {
+                    return Err(self.err(format!("Undefined property '{name}'.")));
+                };
+                let bound = Value::BoundMethod(Rc::new(BoundMethod { receiver, method }));
+                self.register(bound.clone());
+                self.push(bound)?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// With string coercion enabled, formats a number/bool/nil operand of
+    /// `+` as a string when the other operand is already one.
+    fn coerce_add_operands(&mut self, a: Value, b: Value) -> (Value, Value) {
+        match (&a, &b) {
+            (Value::String(_), Value::Float(_) | Value::Bool(_) | Value::Nil) => {
+                let b = Value::String(self.intern(&b.to_string()));
+                (a, b)
+            }
+            (Value::Float(_) | Value::Bool(_) | Value::Nil, Value::String(_)) => {
+                let a = Value::String(self.intern(&a.to_string()));
+                (a, b)
+            }
+            _ => (a, b),
+        }
+    }
+
+    /// Dispatches a binary operator to `a`'s overload method (`add`, `eq`,
+    /// `lt`, ...) when `a` is an instance whose class defines it, pushing the
+    /// method's result. Returns false when there is no overload, leaving the
+    /// built-in behavior to the caller.
+    fn try_binary_overload(
+        &mut self,
+        name: &str,
+        a: &Value,
+        b: &Value,
+    ) -> Result<bool, InterpretError> {
+        let Value::Instance(instance) = a else {
+            return Ok(false);
+        };
+        let method = instance.class.find_method(name);
+        let Some(Value::Closure(method)) = method else {
+            return Ok(false);
+        };
+        let base = self.frames.len();
+        self.push(a.clone())?;
+        self.push(b.clone())?;
+        self.call_closure(method, 1)?;
+        let result = self.run(base)?;
+        self.push(result)?;
+        Ok(true)
+    }
+
+    /// Validates a list index: a whole number in `0..len`.
+    fn check_index(&self, idx: &Value, len: usize) -> Result<usize, InterpretError> {
+        let Value::Float(i) = idx else {
+            return Err(self.err("List index must be a number."));
+        };
+        if fract(*i) != 0.0 || *i < 0.0 || (*i as usize) >= len {
+            return Err(self.err(format!("List index {idx} out of range.")));
+        }
+        Ok(*i as usize)
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: u8) -> Result<(), InterpretError> {
+        match callee {
+            Value::Closure(closure) => self.call_closure(closure, arg_count),
+            Value::BoundMethod(bound) => {
+                let slot = self.stack.cursor - arg_count as usize - 1;
+                self.stack.set(slot, bound.receiver.clone());
+                self.call_closure(Rc::clone(&bound.method), arg_count)
+            }
+            Value::Class(class) => {
+                let instance = Value::Instance(Rc::new(Instance::new(Rc::clone(&class))));
+                self.register(instance.clone());
+                let slot = self.stack.cursor - arg_count as usize - 1;
+                self.stack.set(slot, instance);
+                let init = class.find_method("init");
+                if let Some(Value::Closure(init)) = init {
+                    self.call_closure(init, arg_count)
+                } else if arg_count != 0 {
+                    Err(self.err(format!(
+                        "Function({}) expects 0 args, got {arg_count}.",
+                        class.name
+                    )))
+                } else {
+                    Ok(())
+                }
+            }
+            Value::NativeFn(native) => {
+                let argc = arg_count as usize;
+                let args: Vec<Value> =
+                    self.stack.data[self.stack.cursor - argc..self.stack.cursor].to_vec();
+                let result = (native.func)(self, &args).map_err(|msg| self.err(msg))?;
+                self.stack.truncate(self.stack.cursor - argc - 1);
+                self.push(result)
+            }
+            _ => Err(self.err("Can only call functions and classes.")),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        mut arg_count: u8,
+    ) -> Result<(), InterpretError> {
+        let function = &closure.function;
+        if function.is_variadic {
+            let required = function.arg_count - 1;
+            if arg_count < required {
+                return Err(self.err(format!(
+                    "Function({}) expects at least {required} args, got {arg_count}.",
+                    function.name_str(),
+                )));
+            }
+            // gather the surplus into the rest-parameter list
+            let surplus = (arg_count - required) as usize;
+            let items = self
+                .stack
+                .data
+                .drain(self.stack.cursor - surplus..self.stack.cursor)
+                .collect::<Vec<_>>();
+            self.stack.cursor -= surplus;
+            let rest = Value::List(Rc::new(RefCell::new(items)));
+            self.register(rest.clone());
+            self.push(rest)?;
+            arg_count = function.arg_count;
+        } else if arg_count != function.arg_count {
+            // name the missing parameters when the caller came up short
+            let missing = function
+                .params
+                .get(arg_count as usize..)
+                .unwrap_or_default()
+                .iter()
+                .map(|p| format!("'{p}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let detail = if missing.is_empty() {
+                String::new()
+            } else {
+                format!(" (missing {missing})")
+            };
+            return Err(self.err(format!(
+                "Function({}) expects {} args, got {arg_count}{detail}.",
+                function.name_str(),
+                function.arg_count
+            )));
+        }
+        if self.frames.len() == self.config.max_frames {
+            return Err(self.err("Stack overflow."));
+        }
+        let sp = self.stack.cursor - arg_count as usize - 1;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            sp,
+        });
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------
+    // upvalues
+    // ------------------------------------------------------------------
+
+    fn capture_upval(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upval in &self.open_upvalues {
+            if matches!(&*upval.borrow(), Upvalue::Open(s) if *s == slot) {
+                return Rc::clone(upval);
+            }
+        }
+        let upval = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upval));
+        upval
+    }
+
+    /// Closes every open upvalue pointing at a stack slot >= `from`.
+    fn close_upvalues(&mut self, from: usize) {
+        let mut i = 0;
+        while i < self.open_upvalues.len() {
+            let slot = match &*self.open_upvalues[i].borrow() {
+                Upvalue::Open(slot) => Some(*slot),
+                Upvalue::Closed(_) => None,
+            };
+            match slot {
+                Some(slot) if slot >= from => {
+                    let value = self.stack.get(slot).clone();
+                    let upval = self.open_upvalues.swap_remove(i);
+                    *upval.borrow_mut() = Upvalue::Closed(value);
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // GC
+    // ------------------------------------------------------------------
+
+    /// Tracks a freshly allocated heap value, possibly triggering collection.
+    fn register(&mut self, value: Value) {
+        self.gc_stats.bytes_allocated += value.size();
+        self.heap_objects.push(value);
+        self.maybe_collect();
+    }
+
+    fn maybe_collect(&mut self) {
+        if self.gc_stats.bytes_allocated > self.gc_stats.next_gc {
+            self.collect_garbage();
+        }
+    }
+
+    /// Drops heap objects (and intern-table entries) that nothing else
+    /// references. Reference counting supplies liveness, so there is no mark
+    /// phase; cyclic garbage is not reclaimed.
+    pub fn collect_garbage(&mut self) {
+        if self.collecting {
+            return;
+        }
+        self.collecting = true;
+        self.run_finalizers();
+        // a dead interned string is held only by the intern table and the
+        // heap-object list
+        self.strings.retain_keys(|key| Rc::strong_count(key) > 2);
+        self.strings.maybe_shrink();
+        self.sweep();
+        self.gc_stats.next_gc = (self.gc_stats.bytes_allocated * self.config.gc_grow_factor)
+            .max(self.config.initial_gc_threshold);
+        self.collecting = false;
+    }
+
+    /// Runs `finalize` on every dying instance whose class defines one,
+    /// before `sweep` frees it. Each instance is finalized at most once: a
+    /// finalizer that resurrects its object (stores `this` somewhere
+    /// reachable) keeps it alive, but the finalizer will not run again when
+    /// the object dies for good. A finalizer that errors is logged and
+    /// skipped; it cannot abort the collection.
+    fn run_finalizers(&mut self) {
+        // gather first: re-entering the VM can allocate, which would
+        // invalidate an iterator over the heap-object list
+        let pending: Vec<Rc<Instance>> = self
+            .heap_objects
+            .iter()
+            .filter_map(|object| match object {
+                Value::Instance(instance)
+                    if Rc::strong_count(instance) == 1
+                        && !instance.finalized.get()
+                        && instance.class.find_method("finalize").is_some() =>
+                {
+                    Some(Rc::clone(instance))
+                }
+                _ => None,
+            })
+            .collect();
+        for instance in pending {
+            instance.finalized.set(true);
+            let Some(Value::Closure(method)) = instance.class.find_method("finalize") else {
+                continue;
+            };
+            if let Err(error) = self.call_finalizer(instance, method) {
+                tracing::error!("error in finalizer: {error:?}");
+            }
+        }
+    }
+
+    /// Calls `method` with `instance` as its receiver, unwinding any error
+    /// so the collection can proceed with a clean stack.
+    fn call_finalizer(
+        &mut self,
+        instance: Rc<Instance>,
+        method: Rc<Closure>,
+    ) -> Result<(), InterpretError> {
+        let base = self.frames.len();
+        let entry = self.stack.cursor;
+        let result = (|| {
+            // the receiver doubles as the callee slot
+            self.push(Value::Instance(instance))?;
+            self.call_closure(method, 0)?;
+            self.run(base).map(|_| ())
+        })();
+        if result.is_err() {
+            self.close_upvalues(entry);
+            self.frames.truncate(base);
+            self.stack.truncate(entry);
+            while matches!(self.handlers.last(), Some(h) if h.frame_depth > base) {
+                self.handlers.pop();
+            }
+            self.thrown = None;
+        }
+        result
+    }
+
+    fn sweep(&mut self) {
+        // single retain pass: computes each dead object's size exactly once
+        // and compacts without reordering the survivors
+        let mut freed = 0;
+        self.heap_objects.retain(|object| {
+            if object.refcount() == Some(1) {
+                freed += object.size();
+                false
+            } else {
+                true
+            }
+        });
+        self.gc_stats.bytes_allocated -= freed;
+    }
+}
@@ -0,0 +1,486 @@
+//! Native (Rust-implemented) functions exposed to Lox programs. Registered by
+//! `VM::init_natives`. All natives share the `NativeFunc` signature; returning
+//! `Err` raises an `InterpretError::RuntimeError` in the calling script.
+
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::value::{fract, Value};
+use crate::vm::VM;
+
+/// Monotonic seconds since the VM was created, as a float. Suitable for
+/// benchmark deltas, unlike wall-clock [`time`].
+#[cfg(feature = "std")]
+pub fn clock(vm: &mut VM, _args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Float(vm.start.elapsed().as_secs_f64()))
+}
+
+/// Wall-clock seconds since the Unix epoch, as a float. Negative for clocks
+/// set before the epoch rather than panicking.
+#[cfg(feature = "std")]
+pub fn time(_vm: &mut VM, _args: &[Value]) -> Result<Value, String> {
+    let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs_f64(),
+        Err(before) => -before.duration().as_secs_f64(),
+    };
+    Ok(Value::Float(seconds))
+}
+
+/// User-facing type name of a value. All callable flavors report
+/// `"function"`.
+pub fn type_of(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let name = match args.first() {
+        Some(Value::Nil) | None => "nil",
+        Some(Value::Bool(_)) => "bool",
+        Some(Value::Float(_)) => "number",
+        Some(Value::String(_)) => "string",
+        Some(
+            Value::Function(_) | Value::Closure(_) | Value::NativeFn(_) | Value::BoundMethod(_),
+        ) => "function",
+        Some(Value::Class(_)) => "class",
+        Some(Value::Instance(_)) => "instance",
+        Some(Value::List(_)) => "list",
+        Some(Value::Weak(_)) => "weakref",
+    };
+    Ok(Value::String(vm.intern(name)))
+}
+
+/// `assert(cond)` / `assert(cond, message)`: raises a runtime error when
+/// `cond` is falsey.
+pub fn assert(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [cond] | [cond, _] if cond.is_truthy() => Ok(Value::Nil),
+        [_] => Err("Assertion failed.".to_string()),
+        [_, message] => Err(message.to_string()),
+        _ => Err(format!("Function(assert) expects 1 or 2 args, got {}.", args.len())),
+    }
+}
+
+/// `format(fmt, ...)`: replaces each `{}` in `fmt` with the next argument's
+/// display form.
+pub fn format(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::String(fmt)) = args.first() else {
+        return Err("format() expects a format string as its first argument.".to_string());
+    };
+    let mut out = String::with_capacity(fmt.len());
+    let mut values = args[1..].iter();
+    let mut rest = &fmt[..];
+    while let Some(idx) = rest.find("{}") {
+        out.push_str(&rest[..idx]);
+        match values.next() {
+            Some(value) => out.push_str(&value.to_string()),
+            None => return Err("Not enough arguments for format string.".to_string()),
+        }
+        rest = &rest[idx + 2..];
+    }
+    out.push_str(rest);
+    if values.next().is_some() {
+        return Err("Too many arguments for format string.".to_string());
+    }
+    Ok(Value::String(vm.intern(&out)))
+}
+
+/// `split(s, sep)`: list of the pieces of `s` between occurrences of `sep`.
+/// An empty separator splits into individual characters.
+pub fn split(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (Some(Value::String(s)), Some(Value::String(sep))) = (args.first(), args.get(1)) else {
+        return Err("split() expects two string arguments.".to_string());
+    };
+    let s = Rc::clone(s);
+    let pieces: Vec<Value> = if sep.is_empty() {
+        s.chars()
+            .map(|c| Value::String(vm.intern(c.encode_utf8(&mut [0; 4]))))
+            .collect()
+    } else {
+        s.split(sep.as_ref())
+            .map(|piece| Value::String(vm.intern(piece)))
+            .collect()
+    };
+    let list = Value::List(Rc::new(RefCell::new(pieces)));
+    vm.register(list.clone());
+    Ok(list)
+}
+
+/// `chars(s)`: list of `s`'s characters as single-character strings, split
+/// on Unicode scalar boundaries so multibyte characters stay intact.
+pub fn chars(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::String(s)) = args.first() else {
+        return Err("chars() expects a string argument.".to_string());
+    };
+    let s = Rc::clone(s);
+    let items: Vec<Value> = s
+        .chars()
+        .map(|c| Value::String(vm.intern(c.encode_utf8(&mut [0; 4]))))
+        .collect();
+    let list = Value::List(Rc::new(RefCell::new(items)));
+    vm.register(list.clone());
+    Ok(list)
+}
+
+/// `fields(obj)`: list of an instance's field names, in table order, for
+/// reflection and serialization.
+pub fn fields(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Instance(instance)) = args.first() else {
+        return Err("fields() expects an instance argument.".to_string());
+    };
+    let names: Vec<Value> = instance
+        .fields
+        .borrow()
+        .iter()
+        .map(|(name, _)| Value::String(Rc::clone(name)))
+        .collect();
+    let list = Value::List(Rc::new(RefCell::new(names)));
+    vm.register(list.clone());
+    Ok(list)
+}
+
+/// `is(obj, class)`: whether `obj` is an instance of `class` or of one of
+/// its subclasses, walking the superclass chain recorded by `Inherit`.
+pub fn is(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (Some(Value::Instance(instance)), Some(Value::Class(class))) =
+        (args.first(), args.get(1))
+    else {
+        return Err("is() expects an instance and a class.".to_string());
+    };
+    let mut current = Some(Rc::clone(&instance.class));
+    while let Some(c) = current {
+        if Rc::ptr_eq(&c, class) {
+            return Ok(Value::Bool(true));
+        }
+        current = c.superclass.borrow().clone();
+    }
+    Ok(Value::Bool(false))
+}
+
+/// `sleep(seconds)`: blocks the interpreter thread. `sleep(0)` is a no-op.
+#[cfg(feature = "std")]
+pub fn sleep(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Float(seconds)) = args.first() else {
+        return Err("sleep() expects a number of seconds.".to_string());
+    };
+    if !seconds.is_finite() || *seconds < 0.0 {
+        return Err("sleep() expects a non-negative number of seconds.".to_string());
+    }
+    if *seconds > 0.0 {
+        std::thread::sleep(std::time::Duration::from_secs_f64(*seconds));
+    }
+    Ok(Value::Nil)
+}
+
+/// `print(x)`: the statement's functional twin, so printing can be stored
+/// in variables and passed to higher-order functions.
+pub fn print(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    #[cfg(feature = "std")]
+    use std::io::Write;
+
+    let value = args.first().cloned().unwrap_or(Value::Nil);
+    let _ = writeln!(vm.out, "{value}");
+    Ok(Value::Nil)
+}
+
+/// `debug(x)`: the value's `Debug` form as a string — `Float(123.0)` rather
+/// than `123` — so scripts can see type distinctions `Display` hides.
+pub fn debug(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let value = args.first().cloned().unwrap_or(Value::Nil);
+    Ok(Value::String(vm.intern(&format!("{value:?}"))))
+}
+
+/// `freeze(obj)`: marks an instance immutable, returning it. Field writes
+/// (including through `set()`) error afterwards; reads and method calls are
+/// unaffected. There is no thaw.
+pub fn freeze(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Instance(instance)) = args.first() else {
+        return Err("freeze() expects an instance argument.".to_string());
+    };
+    instance.frozen.set(true);
+    Ok(args[0].clone())
+}
+
+/// `weakref(obj)`: a non-owning handle to an instance, for caches that must
+/// not keep their entries alive.
+pub fn weakref(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Instance(instance)) = args.first() else {
+        return Err("weakref() expects an instance argument.".to_string());
+    };
+    Ok(Value::Weak(Rc::downgrade(instance)))
+}
+
+/// `deref(w)`: the weakref's instance while it is still strongly reachable,
+/// `nil` once it has been collected.
+pub fn deref(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Weak(weak)) = args.first() else {
+        return Err("deref() expects a weakref argument.".to_string());
+    };
+    Ok(match weak.upgrade() {
+        Some(instance) => Value::Instance(instance),
+        None => Value::Nil,
+    })
+}
+
+/// `superclass(x)`: the superclass of a class (or of an instance's class),
+/// or `nil` at the root of the chain.
+pub fn superclass(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let class = match args.first() {
+        Some(Value::Class(class)) => Rc::clone(class),
+        Some(Value::Instance(instance)) => Rc::clone(&instance.class),
+        _ => return Err("superclass() expects a class or an instance.".to_string()),
+    };
+    let parent = class.superclass.borrow().clone();
+    Ok(match parent {
+        Some(superclass) => Value::Class(superclass),
+        None => Value::Nil,
+    })
+}
+
+/// `has(obj, name)`: whether the instance currently has a field `name`.
+/// Methods don't count — this is field reflection, not property lookup.
+pub fn has(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (instance, name) = reflection_target(args, "has")?;
+    Ok(Value::Bool(instance.fields.borrow().get(name).is_some()))
+}
+
+/// `get(obj, name)`: the instance's field `name`, or `nil` when absent.
+pub fn get(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (instance, name) = reflection_target(args, "get")?;
+    Ok(instance
+        .fields
+        .borrow()
+        .get(name)
+        .cloned()
+        .unwrap_or(Value::Nil))
+}
+
+/// `set(obj, name, value)`: writes the instance's field `name`, creating it
+/// when absent, and returns the value.
+pub fn set(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let value = args
+        .get(2)
+        .ok_or_else(|| "set() expects an instance, a string field name, and a value.".to_string())?
+        .clone();
+    let (instance, name) = reflection_target(args, "set")?;
+    let instance = Rc::clone(instance);
+    if instance.frozen.get() {
+        return Err("Cannot write property of frozen instance.".to_string());
+    }
+    let key = vm.intern(name);
+    instance.fields.borrow_mut().set(key, value.clone());
+    Ok(value)
+}
+
+/// Shared argument validation for the `has`/`get`/`set` reflection natives.
+fn reflection_target<'a>(
+    args: &'a [Value],
+    native: &str,
+) -> Result<(&'a Rc<crate::value::Instance>, &'a str), String> {
+    match (args.first(), args.get(1)) {
+        (Some(Value::Instance(instance)), Some(Value::String(name))) => Ok((instance, name)),
+        _ => Err(format!(
+            "{native}() expects an instance and a string field name."
+        )),
+    }
+}
+
+/// `ord(s)`: Unicode scalar value of a single-character string.
+pub fn ord(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::String(s)) = args.first() else {
+        return Err("ord() expects a string argument.".to_string());
+    };
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::Float(c as u32 as f64)),
+        _ => Err("ord() expects a single-character string.".to_string()),
+    }
+}
+
+/// `chr(n)`: single-character string for the Unicode scalar value `n`.
+pub fn chr(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Float(n)) = args.first() else {
+        return Err("chr() expects a number argument.".to_string());
+    };
+    let c = (fract(*n) == 0.0 && *n >= 0.0)
+        .then(|| char::from_u32(*n as u32))
+        .flatten();
+    let Some(c) = c else {
+        return Err(format!("chr() argument {n} is not a Unicode scalar value."));
+    };
+    Ok(Value::String(vm.intern(c.encode_utf8(&mut [0; 4]))))
+}
+
+/// `substr(s, start, len)`: up to `len` characters of `s` beginning at
+/// character index `start`. Indices are counted in characters, not bytes, so
+/// multibyte strings never split mid-character; ranges past the end clamp to
+/// it rather than erroring.
+pub fn substr(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (Some(Value::String(s)), Some(Value::Float(start)), Some(Value::Float(len))) =
+        (args.first(), args.get(1), args.get(2))
+    else {
+        return Err("substr() expects a string, a start, and a length.".to_string());
+    };
+    if fract(*start) != 0.0 || *start < 0.0 || fract(*len) != 0.0 || *len < 0.0 {
+        return Err("substr() start and length must be whole non-negative numbers.".to_string());
+    }
+    let piece: String = s
+        .chars()
+        .skip(*start as usize)
+        .take(*len as usize)
+        .collect();
+    Ok(Value::String(vm.intern(&piece)))
+}
+
+/// `contains(s, needle)`: whether `needle` occurs in `s`.
+pub fn contains(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (s, needle) = two_strings(args, "contains")?;
+    Ok(Value::Bool(s.contains(needle)))
+}
+
+/// `index_of(s, needle)`: character index of the first occurrence of
+/// `needle` in `s`, or `-1`. An empty needle is found at index 0.
+pub fn index_of(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let (s, needle) = two_strings(args, "index_of")?;
+    let idx = match s.find(needle) {
+        Some(byte_idx) => s[..byte_idx].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::Float(idx))
+}
+
+fn two_strings<'a>(args: &'a [Value], name: &str) -> Result<(&'a str, &'a str), String> {
+    match (args.first(), args.get(1)) {
+        (Some(Value::String(s)), Some(Value::String(needle))) => Ok((s, needle)),
+        _ => Err(format!("{name}() expects two string arguments.")),
+    }
+}
+
+/// `min(a, b, ...)`: smallest of the numeric arguments. `NaN` propagates:
+/// any `NaN` argument makes the result `NaN`.
+pub fn min(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    fold_numbers(args, "min", |acc, x| if x.is_nan() { x } else { acc.min(x) })
+}
+
+/// `max(a, b, ...)`: largest of the numeric arguments. `NaN` propagates:
+/// any `NaN` argument makes the result `NaN`.
+pub fn max(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    fold_numbers(args, "max", |acc, x| if x.is_nan() { x } else { acc.max(x) })
+}
+
+/// `clamp(x, lo, hi)`: `x` limited to `[lo, hi]`. Inverted bounds are an
+/// error rather than a silent swap.
+pub fn clamp(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let [Value::Float(x), Value::Float(lo), Value::Float(hi)] = args else {
+        return Err("clamp() expects number arguments (x, lo, hi).".to_string());
+    };
+    if lo > hi {
+        return Err(format!("clamp() expects lo <= hi, got {lo} > {hi}."));
+    }
+    Ok(Value::Float(x.clamp(*lo, *hi)))
+}
+
+/// `lerp(a, b, t)`: linear interpolation from `a` to `b` by `t`. `t` is not
+/// clamped, so values outside `[0, 1]` extrapolate.
+pub fn lerp(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let [Value::Float(a), Value::Float(b), Value::Float(t)] = args else {
+        return Err("lerp() expects number arguments (a, b, t).".to_string());
+    };
+    Ok(Value::Float(a + (b - a) * t))
+}
+
+/// `sign(x)`: `-1`, `0`, or `1` by the sign of `x`; `NaN` passes through.
+pub fn sign(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Float(x)) = args.first() else {
+        return Err("sign() expects a number argument.".to_string());
+    };
+    let s = if *x > 0.0 {
+        1.0
+    } else if *x < 0.0 {
+        -1.0
+    } else {
+        *x
+    };
+    Ok(Value::Float(s))
+}
+
+fn fold_numbers(
+    args: &[Value],
+    name: &str,
+    f: impl Fn(f64, f64) -> f64,
+) -> Result<Value, String> {
+    if args.is_empty() {
+        return Err(format!("{name}() expects at least one argument."));
+    }
+    let mut acc: Option<f64> = None;
+    for arg in args {
+        let Value::Float(x) = arg else {
+            return Err(format!("{name}() expects number arguments."));
+        };
+        acc = Some(match acc {
+            Some(acc) if !acc.is_nan() => f(acc, *x),
+            Some(acc) => acc,
+            None => *x,
+        });
+    }
+    Ok(Value::Float(acc.unwrap()))
+}
+
+/// `arity(f)`: parameter count of a function, closure, or bound method.
+/// Natives have unknown arity and report `nil`.
+pub fn arity(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    match args.first() {
+        Some(Value::Function(f)) => Ok(Value::Float(f.arg_count as f64)),
+        Some(Value::Closure(c)) => Ok(Value::Float(c.function.arg_count as f64)),
+        Some(Value::BoundMethod(b)) => Ok(Value::Float(b.method.function.arg_count as f64)),
+        Some(Value::NativeFn(_)) => Ok(Value::Nil),
+        _ => Err("arity() expects a function argument.".to_string()),
+    }
+}
+
+/// `len(x)`: element count of a list or byte length of a string.
+pub fn len(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    match args.first() {
+        Some(Value::List(list)) => Ok(Value::Float(list.borrow().len() as f64)),
+        Some(Value::String(s)) => Ok(Value::Float(s.len() as f64)),
+        _ => Err("len() expects a list or string argument.".to_string()),
+    }
+}
+
+/// `seed_rng(n)`: reseeds the deterministic RNG. The same seed always
+/// produces the same `random()` sequence.
+pub fn seed_rng(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Float(seed)) = args.first() else {
+        return Err("seed_rng() expects a number argument.".to_string());
+    };
+    // avoid the xorshift fixed point at zero
+    vm.rng_state = (seed.to_bits()).max(1);
+    Ok(Value::Nil)
+}
+
+/// `random()`: the next value in `[0, 1)` from a seedable xorshift64* stream
+/// kept on the VM.
+pub fn random(vm: &mut VM, _args: &[Value]) -> Result<Value, String> {
+    let mut x = vm.rng_state;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    vm.rng_state = x;
+    let bits = x.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    Ok(Value::Float((bits >> 11) as f64 / (1u64 << 53) as f64))
+}
+
+/// `num(s)`: parses a string into a number, trimming surrounding whitespace.
+pub fn num(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::String(text)) = args.first() else {
+        return Err("num() expects a string argument.".to_string());
+    };
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Err("Cannot convert empty string to a number.".to_string());
+    }
+    trimmed
+        .parse::<f64>()
+        .map(Value::Float)
+        .map_err(|_| format!("Cannot convert '{trimmed}' to a number."))
+}
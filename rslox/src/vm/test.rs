@@ -0,0 +1,448 @@
+use crate::test_utils::*;
+use crate::value::Value;
+use crate::vm::{ErrorKind, InterpretError, VMConfig, VM};
+
+#[test]
+fn call_stack() {
+    expect_printed(
+        r#"
+        fun a() { print "a before"; b(); print "a after"; }
+        fun b() { print "b before"; c(); print "b after"; }
+        fun c() { print "c"; }
+        a();
+        "#,
+        "a before\nb before\nc\nb after\na after\n",
+    );
+}
+
+#[test]
+fn ret_statement() {
+    expect_printed(
+        r#"
+        fun pick(flag) {
+            if (flag) return "yes";
+            return "no";
+        }
+        print pick(true);
+        print pick(false);
+        fun nothing() { return; }
+        print nothing();
+        "#,
+        "yes\nno\nnil\n",
+    );
+}
+
+#[test]
+fn call_function_from_rust() {
+    let mut vm = VM::new();
+    vm.interpret("fun add(a, b) { return a + b; }").unwrap();
+    let result = vm
+        .call_function("add", &[Value::Float(2.0), Value::Float(3.0)])
+        .unwrap();
+    assert_eq!(result, Value::Float(5.0));
+}
+
+#[test]
+fn call_function_repeatedly() {
+    let mut vm = VM::new();
+    vm.interpret("var count = 0; fun bump() { count = count + 1; return count; }")
+        .unwrap();
+    assert_eq!(vm.call_function("bump", &[]), Ok(Value::Float(1.0)));
+    assert_eq!(vm.call_function("bump", &[]), Ok(Value::Float(2.0)));
+}
+
+#[test]
+fn call_function_native() {
+    let mut vm = VM::new();
+    let result = vm.call_function("typeof", &[Value::Nil]).unwrap();
+    assert_eq!(result.to_string(), "nil");
+}
+
+#[test]
+fn call_function_errors() {
+    let mut vm = VM::new();
+    vm.interpret("fun add(a, b) { return a + b; }").unwrap();
+    assert!(matches!(
+        vm.call_function("missing", &[]),
+        Err(InterpretError::RuntimeError { msg, .. }) if msg.contains("Undefined variable 'missing'.")
+    ));
+    assert!(matches!(
+        vm.call_function("add", &[Value::Float(1.0)]),
+        Err(InterpretError::RuntimeError { msg, .. }) if msg.contains("expects 2 args, got 1 (missing 'b').")
+    ));
+    // the failed calls must not corrupt the VM
+    assert_eq!(
+        vm.call_function("add", &[Value::Float(2.0), Value::Float(3.0)]),
+        Ok(Value::Float(5.0))
+    );
+}
+
+#[test]
+fn profiler_tallies_opcodes() {
+    use crate::chunk::OpCode;
+
+    let mut vm = VM::new();
+    vm.profile = true;
+    vm.interpret("var total = 0; for (var i = 0; i < 10; i = i + 1) { total = total + i; }")
+        .unwrap();
+    let counts = vm.opcode_counts();
+    // two adds per iteration: the accumulation and the increment
+    assert_eq!(counts[OpCode::Add as usize], 20);
+    // each iteration jumps back twice: body -> increment -> condition
+    assert_eq!(counts[OpCode::JumpBack as usize], 20);
+}
+
+#[test]
+fn dump_lists_script_and_nested_functions() {
+    let mut vm = VM::new();
+    let text = vm
+        .dump("fun outer() { fun inner() { return 1; } return inner; } print outer()();")
+        .unwrap();
+    assert!(text.contains("== script =="), "{text}");
+    assert!(text.contains("== outer =="), "{text}");
+    assert!(text.contains("== inner =="), "{text}");
+    assert!(text.contains("Return"), "{text}");
+}
+
+#[test]
+fn dump_headers_carry_starting_lines() {
+    let mut vm = VM::new();
+    let text = vm
+        .dump("var a = 1;\nvar b = 2;\nfun inner() { return 5; }\nprint inner();")
+        .unwrap();
+    assert!(text.contains("== script == (line 1)"), "{text}");
+    assert!(text.contains("== inner == (line 3)"), "{text}");
+}
+
+#[test]
+fn doc_comment_attaches_to_function() {
+    let mut vm = VM::new();
+    vm.interpret(
+        "/* Doubles a number. */\nfun double(x) { return x + x; }\nfun bare() { }",
+    )
+    .unwrap();
+    assert_eq!(vm.doc_for("double").as_deref(), Some("Doubles a number."));
+    assert_eq!(vm.doc_for("bare"), None);
+    assert_eq!(vm.doc_for("missing"), None);
+}
+
+#[test]
+fn disassemble_function_by_name() {
+    let mut vm = VM::new();
+    vm.interpret("fun double(x) { return x + x; } fun shout(s) { print s; }")
+        .unwrap();
+    let dump = vm.disassemble_function("double").unwrap();
+    assert!(dump.contains("== double =="), "{dump}");
+    assert!(dump.contains("Add"), "{dump}");
+    assert!(!dump.contains("Print"), "{dump}");
+    assert!(vm.disassemble_function("missing").is_none());
+}
+
+#[test]
+fn dup_copies_top_of_stack() {
+    use std::rc::Rc;
+
+    use crate::chunk::OpCode;
+    use crate::value::{Closure, Function};
+    use crate::vm::CallFrame;
+
+    // hand-assembled since the compiler has no construct that emits Dup yet:
+    // push 42, duplicate it, compare the two copies, return the result
+    let mut function = Function::new(None, Rc::from(""));
+    function.chunk.constants.push(Value::Float(42.0));
+    function.chunk.push_op(OpCode::Constant, 1);
+    function.chunk.push_byte(0, 1);
+    function.chunk.push_op(OpCode::Dup, 1);
+    function.chunk.push_op(OpCode::Equal, 1);
+    function.chunk.push_op(OpCode::Return, 1);
+
+    let mut vm = VM::new();
+    let closure = Rc::new(Closure {
+        function: Rc::new(function),
+        upvalues: Vec::new(),
+    });
+    vm.stack.push(Value::Closure(Rc::clone(&closure))).unwrap();
+    vm.frames.push(CallFrame {
+        closure,
+        ip: 0,
+        sp: 0,
+    });
+    assert_eq!(vm.run(0), Ok(Value::Bool(true)));
+}
+
+#[test]
+fn intern_str_returns_pointer_equal_values() {
+    use std::rc::Rc;
+
+    let mut vm = VM::new();
+    let a = vm.intern_str("shared");
+    let b = vm.intern_str("shared");
+    match (&a, &b) {
+        (Value::String(a), Value::String(b)) => assert!(Rc::ptr_eq(a, b)),
+        other => panic!("expected strings, got {other:?}"),
+    }
+    // scripts see the same canonical handle
+    vm.interpret("var s = \"shared\";").unwrap();
+    let script_copy = vm.globals.get(&vm.strings.get_key("s").unwrap()).cloned();
+    match (a, script_copy) {
+        (Value::String(a), Some(Value::String(s))) => assert!(Rc::ptr_eq(&a, &s)),
+        other => panic!("expected strings, got {other:?}"),
+    }
+}
+
+#[test]
+fn globals_snapshot_reflects_final_state() {
+    let mut vm = VM::new();
+    vm.interpret("var x = 42; var s = \"hi\";").unwrap();
+    let snapshot = vm.globals_snapshot();
+    assert!(snapshot.contains(&("x".to_string(), Value::Float(42.0))));
+    let s = snapshot.iter().find(|(name, _)| name == "s").unwrap();
+    assert_eq!(s.1.to_string(), "hi");
+}
+
+#[test]
+fn globals_survive_across_scripts() {
+    let mut vm = VM::new();
+    vm.interpret("var shared = 41;").unwrap();
+    vm.interpret("shared = shared + 1; print shared;").unwrap();
+
+    // a runtime error only discards the in-flight execution
+    assert!(vm.interpret("var kept = 7; missing;").is_err());
+    // accounting stays consistent: a full collection after the partial
+    // reset must not underflow or strand bytes
+    let after_error = vm.gc_stats().bytes_allocated;
+    vm.collect_garbage();
+    assert!(vm.gc_stats().bytes_allocated <= after_error);
+    let out = Capture::default();
+    vm.set_output(Box::new(out.clone()));
+    vm.interpret("print kept + shared;").unwrap();
+    assert_eq!(out.contents(), "49\n");
+}
+
+#[test]
+fn conditions_use_fused_jump_pop() {
+    let mut vm = VM::new();
+    let text = vm
+        .dump("if (1 < 2) print \"yes\"; while (false) print \"no\";")
+        .unwrap();
+    assert!(text.contains("JumpFalseyPop"), "{text}");
+    assert!(!text.contains("JumpFalsey "), "unfused condition jump: {text}");
+}
+
+#[test]
+fn scope_exit_batches_pops() {
+    let mut vm = VM::new();
+    let text = vm
+        .dump("{ var a = 1; var b = 2; var c = 3; print a + b + c; }")
+        .unwrap();
+    assert!(text.contains("StackSub (3 slots)"), "{text}");
+
+    let mut vm = VM::new();
+    vm.set_output(Box::new(std::io::sink()));
+    vm.interpret("{ var a = 1; var b = 2; var c = 3; print a + b + c; }")
+        .unwrap();
+    assert_eq!(vm.stack.cursor, 0);
+}
+
+#[test]
+fn coverage_skips_untaken_branches() {
+    let mut vm = VM::new();
+    vm.coverage = true;
+    vm.set_output(Box::new(std::io::sink()));
+    vm.interpret("var x = 1;\nif (x == 1) {\nprint \"then\";\n} else {\nprint \"else\";\n}")
+        .unwrap();
+    let covered = vm.covered_lines();
+    assert!(covered.contains(&3), "covered: {covered:?}");
+    assert!(!covered.contains(&5), "covered: {covered:?}");
+}
+
+#[test]
+fn arity_introspection() {
+    let mut vm = VM::new();
+    vm.interpret("fun f(a, b, c) {}").unwrap();
+    assert_eq!(vm.global_arity("f"), Some(3));
+    assert_eq!(vm.global_arity("clock"), None);
+    assert_eq!(vm.global_arity("missing"), None);
+    assert_eq!(vm.call_function("arity", &[vm.globals.get("f").unwrap().clone()]), Ok(Value::Float(3.0)));
+}
+
+#[test]
+fn runtime_error_exposes_kind_and_line() {
+    let mut vm = VM::new();
+    let result = vm.interpret("var a = 1;\nprint missing;");
+    match result {
+        Err(InterpretError::RuntimeError { kind, line, .. }) => {
+            assert_eq!(kind, ErrorKind::UndefinedVariable);
+            assert_eq!(line, Some(2));
+        }
+        other => panic!("expected runtime error, got {other:?}"),
+    }
+    let result = vm.interpret("fun f(a) {} f(1, 2);");
+    assert!(matches!(
+        result,
+        Err(InterpretError::RuntimeError { kind: ErrorKind::Arity, .. })
+    ));
+}
+
+#[test]
+fn runtime_error_carries_stack_trace() {
+    let mut vm = VM::new();
+    let result = vm.interpret(
+        "fun inner() { missing; }\nfun outer() { inner(); }\nouter();",
+    );
+    match result {
+        Err(InterpretError::RuntimeError { trace: Some(trace), .. }) => {
+            assert!(trace.contains("[line 1] in inner"), "trace: {trace:?}");
+            assert!(trace.contains("[line 2] in outer"), "trace: {trace:?}");
+        }
+        other => panic!("expected runtime error with trace, got {other:?}"),
+    }
+}
+
+#[test]
+fn sleep_zero_is_a_noop() {
+    let mut vm = VM::new();
+    assert_eq!(vm.call_function("sleep", &[Value::Float(0.0)]), Ok(Value::Nil));
+    assert!(matches!(
+        vm.call_function("sleep", &[Value::Float(-1.0)]),
+        Err(InterpretError::RuntimeError { msg, .. })
+            if msg == "sleep() expects a non-negative number of seconds."
+    ));
+}
+
+#[test]
+fn bounded_run_yields_and_resumes() {
+    use crate::vm::VMState;
+
+    let mut vm = VM::new();
+    vm.set_output(Box::new(std::io::sink()));
+    let state = vm
+        .interpret_bounded(
+            "var t = 0; for (var i = 0; i < 100; i = i + 1) { t = t + i; } print t;",
+            10,
+        )
+        .unwrap();
+    assert_eq!(state, VMState::Yielded);
+    let mut slices = 0;
+    while vm.resume(50).unwrap() == VMState::Yielded {
+        slices += 1;
+        assert!(slices < 1000, "execution never finished");
+    }
+    assert_eq!(vm.resume(50), Ok(VMState::Done));
+    assert_eq!(
+        vm.call_function("typeof", &[Value::Nil]).unwrap().to_string(),
+        "nil"
+    );
+}
+
+#[test]
+fn frame_depth_tracks_nested_calls() {
+    let mut vm = VM::new();
+    vm.set_output(Box::new(std::io::sink()));
+    assert_eq!(vm.frame_depth(), 0);
+    assert_eq!(vm.stack_depth(), 0);
+    let mut state = vm
+        .interpret_bounded(
+            "fun inner() { return 1; } fun outer() { return inner(); } print outer();",
+            1,
+        )
+        .unwrap();
+    // sample between slices: the depth must reach script + outer + inner
+    let mut max_depth = vm.frame_depth();
+    while state == crate::vm::VMState::Yielded {
+        max_depth = max_depth.max(vm.frame_depth());
+        assert!(vm.stack_depth() >= vm.frame_depth());
+        state = vm.resume(1).unwrap();
+    }
+    assert_eq!(max_depth, 3, "never observed the nested frame");
+    assert_eq!(vm.frame_depth(), 0);
+    assert_eq!(vm.stack_depth(), 0);
+}
+
+#[cfg(feature = "debug_info")]
+#[test]
+fn local_resolves_by_name_in_current_frame() {
+    use crate::vm::VMState;
+
+    let mut vm = VM::new();
+    vm.set_output(Box::new(std::io::sink()));
+    let mut state = vm
+        .interpret_bounded("fun f() { var a = 41; var b = a + 1; print b; } f();", 1)
+        .unwrap();
+    let mut seen = None;
+    while state == VMState::Yielded {
+        if vm.frame_depth() == 2 {
+            if let Some(value) = vm.local("b") {
+                if value != Value::Nil {
+                    seen = Some(value);
+                }
+            }
+        }
+        state = vm.resume(1).unwrap();
+    }
+    assert_eq!(seen, Some(Value::Float(42.0)));
+}
+
+#[cfg(feature = "debug_info")]
+#[test]
+fn local_debug_records_carry_scope_extents() {
+    let mut vm = VM::new();
+    let script = vm
+        .compile("fun f() { var a = 1; { var b = 2; print b; } print a; }")
+        .unwrap();
+    let func = script
+        .chunk
+        .constants
+        .iter()
+        .find_map(|c| match c {
+            Value::Function(f) => Some(f.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let a = func.chunk.locals.iter().find(|l| l.name == "a").unwrap();
+    let b = func.chunk.locals.iter().find(|l| l.name == "b").unwrap();
+    assert_eq!(a.slot, 1);
+    assert_eq!(b.slot, 2);
+    // b's scope opens after a's and closes no later
+    assert!(a.scope_start_offset < b.scope_start_offset);
+    assert!(b.scope_start_offset < b.scope_end_offset);
+    assert!(b.scope_end_offset <= a.scope_end_offset);
+    // past the inner block only `a` is live
+    let live: Vec<&str> = func
+        .chunk
+        .locals_at(a.scope_end_offset - 1)
+        .map(|l| l.name.as_str())
+        .collect();
+    assert!(live.contains(&"a"), "live: {live:?}");
+    assert!(!live.contains(&"b"), "live: {live:?}");
+}
+
+#[test]
+fn config_caps_call_depth() {
+    let mut vm = VM::with_config(VMConfig {
+        max_frames: 4,
+        ..Default::default()
+    });
+    let source = "fun f(n) { if (n == 0) return 0; return f(n - 1); }";
+    vm.interpret(source).unwrap();
+    // script frame is gone by now, so three levels of recursion fit
+    assert_eq!(vm.call_function("f", &[Value::Float(2.0)]), Ok(Value::Float(0.0)));
+    assert!(matches!(
+        vm.call_function("f", &[Value::Float(10.0)]),
+        Err(InterpretError::RuntimeError { msg, .. }) if msg == "Stack overflow."
+    ));
+}
+
+#[test]
+fn stack_grows_for_deep_recursion() {
+    let mut vm = VM::with_config(VMConfig {
+        max_frames: 2048,
+        ..Default::default()
+    });
+    vm.interpret("fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }")
+        .unwrap();
+    assert_eq!(
+        vm.call_function("f", &[Value::Float(1500.0)]),
+        Ok(Value::Float(1500.0))
+    );
+}